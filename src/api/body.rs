@@ -0,0 +1,67 @@
+use crate::types::{BodyPart, SourceReader};
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Request body that is not held in memory. Set with CallBuilder::body_source.
+///
+/// Data is read in chunks while request is sent. If length is known it is sent
+/// as Content-Length, otherwise body is sent with chunked transfer encoding.
+///
+/// Readers and callbacks can only be read once. If a redirect or retry would need to
+/// send the body again, call fails.
+#[derive(Debug, Clone)]
+pub struct BodySource {
+    part: BodyPart,
+}
+
+// Read implementation for a fill callback.
+struct FillFn<F>(F);
+
+impl<F: FnMut(&mut [u8]) -> io::Result<usize>> Read for FillFn<F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (self.0)(buf)
+    }
+}
+
+impl BodySource {
+    /// File read from disk while request is sent. Length is taken from file metadata.
+    pub fn file<P: AsRef<Path>>(path: P) -> crate::Result<BodySource> {
+        let path = path.as_ref();
+        let meta = fs::metadata(path)?;
+        if !meta.is_file() {
+            return Err(crate::Error::Other("Body source is not a file"));
+        }
+        Ok(BodySource {
+            part: BodyPart::File(path.to_path_buf(), meta.len()),
+        })
+    }
+
+    /// Body read from r. If len is None chunked encoding is used.
+    ///
+    /// Reader is called when socket is writable, a slow reader stalls the event loop.
+    /// If len is set and reader ends sooner, call fails.
+    pub fn reader<R: Read + Send + 'static>(r: R, len: Option<u64>) -> BodySource {
+        BodySource {
+            part: BodyPart::Reader(SourceReader::new(Box::new(r)), len),
+        }
+    }
+
+    /// Body from a callback that fills the given buffer and returns number of bytes
+    /// written to it, 0 once body is complete. If len is None chunked encoding is used.
+    pub fn callback<F>(f: F, len: Option<u64>) -> BodySource
+    where
+        F: FnMut(&mut [u8]) -> io::Result<usize> + Send + 'static,
+    {
+        Self::reader(FillFn(f), len)
+    }
+
+    /// Length of body if known.
+    pub fn content_len(&self) -> Option<u64> {
+        self.part.len()
+    }
+
+    pub(crate) fn finish(self) -> BodyPart {
+        self.part
+    }
+}
@@ -4,6 +4,8 @@ use crate::types::{CallBuilderImpl, Method};
 use crate::SimpleCall;
 use crate::{Call, CallRef, Result};
 use mio::{event::Event, Registry};
+use std::io::IoSlice;
+use std::time::{Duration, Instant};
 
 /// Used to start a call and get a Call for it.
 #[derive(Debug, Default)]
@@ -40,6 +42,10 @@ impl CallBuilder {
         }
     }
 
+    pub(crate) fn from_impl(cb: CallBuilderImpl) -> CallBuilder {
+        CallBuilder { cb: Some(cb) }
+    }
+
     /// Start a GET request.
     pub fn get() -> CallBuilder {
         let mut b = CallBuilder::new();
@@ -84,10 +90,22 @@ impl CallBuilder {
         b
     }
 
-    /// Set method: "GET", "POST", "PUT", "OPTIONS", "DELETE" or "HEAD"
-    pub fn method(&mut self, m: &str) -> &mut Self {
-        self.cb.as_mut().unwrap().method(m);
-        self
+    /// Start a PATCH request.
+    pub fn patch(body: Vec<u8>) -> CallBuilder {
+        let mut b = CallBuilder::new();
+        b.cb.as_mut().unwrap().body = body;
+        b.cb.as_mut().unwrap().method = Method::PATCH;
+        b
+    }
+
+    /// Set method: "GET", "POST", "PUT", "OPTIONS", "DELETE", "HEAD", "PATCH", "TRACE", "CONNECT"
+    /// (case insensitive) or any other valid token as an extension method (like "PROPFIND"),
+    /// which is sent as is.
+    ///
+    /// Returns Error::InvalidMethod if m is not a valid token.
+    pub fn method(&mut self, m: &str) -> crate::Result<&mut Self> {
+        self.cb.as_mut().unwrap().method(m)?;
+        Ok(self)
     }
 
     /// Default: http
@@ -108,6 +126,7 @@ impl CallBuilder {
     }
 
     /// Set host where to connect to. It can be a domain or IP address.
+    /// Call fails to start with Error::InvalidHost if it contains invalid characters.
     pub fn host(&mut self, s: &str) -> &mut Self {
         self.cb.as_mut().unwrap().host(s);
         self
@@ -133,7 +152,9 @@ impl CallBuilder {
         self
     }
 
-    /// Set full path. No percent encoding is done. Will fail later if it contains invalid characters.
+    /// Set full path. No percent encoding is done.
+    /// Call fails to start with Error::InvalidPath if it contains whitespace,
+    /// control or non-ASCII characters.
     pub fn path(&mut self, inpath: &str) -> &mut Self {
         self.cb.as_mut().unwrap().path(inpath);
         self
@@ -186,12 +207,98 @@ impl CallBuilder {
         self
     }
 
-    /// Set HTTP header.
+    /// Set application/x-www-form-urlencoded body and Content-Type header.
+    /// Keys and values are percent encoded, spaces become +.
+    pub fn form(&mut self, kvl: &[(&str, &str)]) -> &mut Self {
+        self.cb.as_mut().unwrap().form(kvl);
+        self
+    }
+
+    /// Set multipart/form-data body and Content-Type header.
+    /// File parts are streamed from disk when request is sent.
+    pub fn multipart(&mut self, m: crate::Multipart) -> &mut Self {
+        self.cb.as_mut().unwrap().multipart(m);
+        self
+    }
+
+    /// Default false
+    ///
+    /// Send Expect: 100-continue with request body and wait for server to accept it
+    /// before sending body. If server replies with a final status (like 401 or 413)
+    /// body is not sent. If server does not reply within expect_continue_ms body is sent anyway.
+    pub fn expect_continue(&mut self, v: bool) -> &mut Self {
+        self.cb.as_mut().unwrap().expect_continue(v);
+        self
+    }
+
+    /// Default 1000.
+    ///
+    /// How long to wait for a reply to Expect: 100-continue before sending body.
+    /// If server sent part of a response in that time call fails with Error::TimeOut.
+    pub fn expect_continue_ms(&mut self, v: u64) -> &mut Self {
+        self.cb.as_mut().unwrap().expect_continue_ms(v);
+        self
+    }
+
+    /// Compress request body (body, form, multipart or body_source) and set Content-Encoding.
+    /// Content-Length is that of compressed body, streamed bodies are sent with
+    /// chunked transfer encoding. A Content-Length header set on call is ignored.
+    /// Data sent with Httpc::call_send is not compressed.
+    pub fn compress_body(&mut self, enc: crate::ContentEncoding) -> &mut Self {
+        self.cb.as_mut().unwrap().compress_body(enc);
+        self
+    }
+
+    /// Set body that is read from a file, reader or callback while request is sent.
+    /// If body length is not known it is sent with chunked transfer encoding.
+    pub fn body_source(&mut self, src: crate::BodySource) -> &mut Self {
+        self.cb.as_mut().unwrap().body_source(src);
+        self
+    }
+
+    /// Add HTTP header. If header is already set it is sent again, use set_header to replace it.
+    ///
+    /// If key is not a valid token or value contains CR, LF or other control characters,
+    /// header is not added and call fails to start with Error::InvalidHeader.
     pub fn header(&mut self, key: &str, value: &str) -> &mut CallBuilder {
         self.cb.as_mut().unwrap().header(key, value);
         self
     }
 
+    /// Set HTTP header, replacing all headers with the same name (case insensitive).
+    /// Setting User-Agent, Host, Connection or Accept-Encoding replaces the automatic header.
+    pub fn set_header(&mut self, key: &str, value: &str) -> &mut CallBuilder {
+        self.cb.as_mut().unwrap().set_header(key, value);
+        self
+    }
+
+    /// Remove all headers with name key (case insensitive).
+    /// Automatic headers (User-Agent, Host, Connection, Accept-Encoding) are sent again.
+    pub fn remove_header(&mut self, key: &str) -> &mut CallBuilder {
+        self.cb.as_mut().unwrap().remove_header(key);
+        self
+    }
+
+    /// Do not send automatic header: User-Agent, Host, Connection or Accept-Encoding.
+    /// Headers with the same name that were set are removed.
+    pub fn suppress_header(&mut self, key: &str) -> &mut CallBuilder {
+        self.cb.as_mut().unwrap().suppress_header(key);
+        self
+    }
+
+    /// First value of header key (case insensitive).
+    /// Automatic headers and Content-Length are not returned.
+    pub fn get_header(&self, key: &str) -> Option<&str> {
+        self.cb.as_ref().unwrap().get_header(key)
+    }
+
+    /// Set HTTP header. Returns Error::InvalidHeader if key is not a valid token
+    /// or value contains CR, LF or other control characters.
+    pub fn try_header(&mut self, key: &str, value: &str) -> Result<&mut CallBuilder> {
+        self.cb.as_mut().unwrap().try_header(key, value)?;
+        Ok(self)
+    }
+
     /// Execute directly. This will block until completion!
     pub fn exec(&mut self) -> crate::Result<(crate::Response, Vec<u8>)> {
         let mut poll = mio::Poll::new()?;
@@ -206,18 +313,20 @@ impl CallBuilder {
                 }
             }
 
+            // While sending, call may be waiting for 100-continue without socket events.
+            let mut ready = !call.is_receiving();
             for ev in events.iter() {
                 let cref = htp.event(&ev);
-
                 if call.is_call(&cref) {
-                    if call.perform(&mut htp, poll.registry())? {
-                        if let Some((resp, v)) = call.finish() {
-                            return Ok((resp, v));
-                        }
-                        return Ok((crate::Response::new(), Vec::new()));
-                    }
+                    ready = true;
                 }
             }
+            if ready && call.perform(&mut htp, poll.registry())? {
+                if let Some((resp, v)) = call.finish() {
+                    return Ok((resp, v));
+                }
+                return Ok((crate::Response::new(), Vec::new()));
+            }
         }
     }
 
@@ -245,8 +354,94 @@ impl CallBuilder {
         // self.finish()?;
         let mut cb = self.cb.take().unwrap();
         cb.websocket();
+        cb.apply_defaults(httpc.h.cfg())?;
+        let opts = cb.ws_opts.clone();
+        let max_msg = if opts.max_message > 0 {
+            opts.max_message
+        } else {
+            cb.max_response
+        };
         let cid = httpc.call::<CONNECTOR>(cb, poll)?;
-        Ok(crate::WebSocket::new(cid, httpc.h.get_buf()))
+        Ok(crate::WebSocket::new(cid, httpc.h.get_buf(), opts, max_msg))
+    }
+
+    /// Consume and start a Server-Sent Events stream.
+    /// CallBuilder is invalid after this call and will panic if used again.
+    pub fn event_source(
+        &mut self,
+        httpc: &mut Httpc,
+        poll: &Registry,
+    ) -> Result<crate::EventSource> {
+        let mut cb = self.cb.take().unwrap();
+        cb.event_source = true;
+        cb.gzip(false);
+        cb.set_header("Accept", "text/event-stream");
+        cb.set_header("Cache-Control", "no-cache");
+        cb.apply_defaults(httpc.h.cfg())?;
+        let cid = httpc.call::<CONNECTOR>(cb.clone(), poll)?;
+        Ok(crate::EventSource::new(cid, cb))
+    }
+
+    /// Default off.
+    ///
+    /// Offer permessage-deflate WebSocket extension (RFC 7692).
+    /// If server accepts it, compressed messages are decompressed and outgoing
+    /// messages are compressed if WsDeflate::compress is set.
+    pub fn ws_deflate(&mut self, v: crate::WsDeflate) -> &mut Self {
+        self.cb.as_mut().unwrap().ws_deflate(v);
+        self
+    }
+
+    /// Default none.
+    ///
+    /// Offer WebSocket subprotocols in order of preference (Sec-WebSocket-Protocol).
+    /// Setup fails if server selects a subprotocol that was not offered.
+    /// Selected subprotocol is returned by WebSocket::protocol.
+    pub fn ws_protocols(&mut self, v: &[&str]) -> &mut Self {
+        self.cb.as_mut().unwrap().ws_protocols(v);
+        self
+    }
+
+    /// Default: max_response
+    ///
+    /// Max size of a received WebSocket message reassembled by WebSocket::recv_message.
+    /// Also limits size of a single decompressed permessage-deflate frame.
+    pub fn ws_max_message(&mut self, v: usize) -> &mut Self {
+        self.cb.as_mut().unwrap().ws_max_message(v);
+        self
+    }
+
+    /// Default false.
+    ///
+    /// Reply to received pings with a pong automatically.
+    /// Ping packets are still returned by recv_packet and recv_message.
+    pub fn ws_auto_pong(&mut self, v: bool) -> &mut Self {
+        self.cb.as_mut().unwrap().ws_auto_pong(v);
+        self
+    }
+
+    /// Default off.
+    ///
+    /// Send a ping if nothing has been received for interval. If nothing is received
+    /// within timeout after the ping, WebSocket fails with Error::WebSocketTimeout.
+    ///
+    /// Pings are sent from WebSocket::perform (or recv_packet). Httpc::timeout returns
+    /// the WebSocket call when a ping is due or timeout passed. Call perform on it instead
+    /// of closing it, WebSocket::keepalive_deadline tells when that will be.
+    pub fn ws_keepalive(&mut self, interval: Duration, timeout: Duration) -> &mut Self {
+        self.cb.as_mut().unwrap().ws_keepalive(interval, timeout);
+        self
+    }
+
+    /// Default unlimited.
+    ///
+    /// Max bytes queued by WebSocket::try_send_text and WebSocket::try_send_bin. Once full they
+    /// return Error::WouldBlock. WebSocket::drained returns true after perform (or recv_packet)
+    /// has sent the queue down to low_water bytes.
+    /// A single packet larger than max is accepted if the queue is empty.
+    pub fn ws_send_queue(&mut self, max: usize, low_water: usize) -> &mut Self {
+        self.cb.as_mut().unwrap().ws_send_queue(max, low_water);
+        self
     }
 
     /// Default 10MB.
@@ -324,6 +519,45 @@ impl CallBuilder {
         self
     }
 
+    /// Default: host from URL.
+    ///
+    /// Name sent in SNI and used for certificate verification and pins.
+    /// Useful when connecting to an IP while the certificate is issued for a domain.
+    pub fn tls_sni(&mut self, name: &str) -> &mut Self {
+        self.cb.as_mut().unwrap().tls_sni(name);
+        self
+    }
+
+    /// Default: TLS backend default.
+    ///
+    /// Lowest TLS version allowed for the connection.
+    /// rustls only supports TLS 1.2 and 1.3.
+    pub fn tls_min_version(&mut self, v: crate::TlsVersion) -> &mut Self {
+        self.cb.as_mut().unwrap().tls_min_version(v);
+        self
+    }
+
+    /// Default: TLS backend default.
+    ///
+    /// Highest TLS version allowed for the connection.
+    pub fn tls_max_version(&mut self, v: crate::TlsVersion) -> &mut Self {
+        self.cb.as_mut().unwrap().tls_max_version(v);
+        self
+    }
+
+    /// Default: TLS backend default.
+    ///
+    /// Allowed cipher suites. Names are backend specific:
+    /// - rustls: suite names like TLS13_AES_256_GCM_SHA384 or TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384.
+    /// - openssl: OpenSSL cipher names like ECDHE-RSA-AES256-GCM-SHA384 or TLS_AES_256_GCM_SHA384.
+    /// - native: not supported, connection will fail.
+    ///
+    /// Unknown names are ignored, connection fails if none of them are supported.
+    pub fn tls_ciphers(&mut self, ciphers: &[&str]) -> &mut Self {
+        self.cb.as_mut().unwrap().tls_ciphers(ciphers);
+        self
+    }
+
     /// Use digest authentication. If you know server is using digest auth you REALLY should set it to true.
     /// If server is using basic authentication and you set digest_auth to true, mio_httpc will retry with basic.
     /// If not set, basic auth is assumed which is very insecure.
@@ -340,6 +574,9 @@ impl CallBuilder {
 
 pub struct Httpc {
     h: crate::httpc::HttpcImpl,
+    // Decoded payload of the last WebSocket frame that could not be returned from
+    // the receive buffer directly.
+    ws_buf: Vec<u8>,
 }
 
 impl Httpc {
@@ -347,6 +584,7 @@ impl Httpc {
     pub fn new(con_offset: usize, cfg: Option<crate::HttpcCfg>) -> Httpc {
         Httpc {
             h: crate::httpc::HttpcImpl::new(con_offset, cfg),
+            ws_buf: Vec::new(),
         }
     }
     pub(crate) fn call<C: TlsConnector>(
@@ -356,9 +594,25 @@ impl Httpc {
     ) -> Result<Call> {
         self.h.call::<C>(b, poll)
     }
+    pub(crate) fn start_call(&mut self, b: CallBuilderImpl, poll: &Registry) -> Result<Call> {
+        self.h.call::<CONNECTOR>(b, poll)
+    }
     pub(crate) fn peek_body(&mut self, id: &crate::Call, off: &mut usize) -> &[u8] {
         self.h.peek_body(id, off)
     }
+    pub(crate) fn peek_body_ws(
+        &mut self,
+        id: &crate::Call,
+        off: &mut usize,
+    ) -> (&[u8], &mut Vec<u8>) {
+        (self.h.peek_body(id, off), &mut self.ws_buf)
+    }
+    pub(crate) fn ws_buf(&self) -> &[u8] {
+        &self.ws_buf
+    }
+    pub(crate) fn set_ws_deadline(&mut self, id: &crate::Call, d: Option<Instant>) {
+        self.h.set_ws_deadline(id, d);
+    }
     pub(crate) fn try_truncate(&mut self, id: &crate::Call, off: &mut usize) {
         self.h.try_truncate(id, off);
     }
@@ -383,6 +637,10 @@ impl Httpc {
     /// You must execute call_close yourself (or SimpleCall::abort) and timeout will return them
     /// every time until you do.
     /// (every 100ms for example)
+    ///
+    /// WebSocket calls with CallBuilder::ws_keepalive are also returned when a ping is due
+    /// or pong did not arrive in time. Call WebSocket::perform for them instead of closing,
+    /// it sends the ping or fails with Error::WebSocketTimeout.
     pub fn timeout(&mut self) -> Vec<CallRef> {
         self.h.timeout()
     }
@@ -418,6 +676,15 @@ impl Httpc {
         self.h.call_send::<CONNECTOR>(poll, id, buf)
     }
 
+    pub(crate) fn call_send_vectored(
+        &mut self,
+        poll: &Registry,
+        id: &mut Call,
+        bufs: &[IoSlice],
+    ) -> crate::SendState {
+        self.h.call_send_vectored::<CONNECTOR>(poll, id, bufs)
+    }
+
     /// If no buf provided, response body (if any) is stored in an internal buffer.
     /// If buf provided after some body has been received, it will be copied to it.
     ///
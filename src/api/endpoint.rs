@@ -0,0 +1,125 @@
+use crate::types::{CallBuilderImpl, Method};
+use crate::{CallBuilder, Result};
+
+/// Base URL with default headers and auth. Calls are derived from it with path_segm,
+/// query or join relative to the base.
+///
+/// ```no_run
+/// # fn main() -> mio_httpc::Result<()> {
+/// let mut api = mio_httpc::Endpoint::new("https://api.example.com/v1")?;
+/// api.header("Authorization", "Bearer token");
+/// // https://api.example.com/v1/users/42?fields=name
+/// let (resp, body) = api
+///     .get()
+///     .path_segms(&["users", "42"])
+///     .query("fields", "name")
+///     .exec()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Endpoint {
+    cb: CallBuilderImpl,
+}
+
+impl Endpoint {
+    /// Base URL. Username and password in URL are used for auth.
+    pub fn new(base: &str) -> Result<Endpoint> {
+        let mut cb = CallBuilderImpl::new();
+        cb.url(base)?;
+        cb.validate()?;
+        Ok(Endpoint { cb })
+    }
+
+    /// Set header sent with every call, replacing headers with the same name.
+    /// Calls can override it with CallBuilder::set_header.
+    pub fn header(&mut self, key: &str, value: &str) -> Result<&mut Self> {
+        self.cb.remove_header(key);
+        self.cb.try_header(key, value)?;
+        Ok(self)
+    }
+
+    /// Use username and password for every call.
+    pub fn auth(&mut self, us: &str, pw: &str) -> &mut Self {
+        self.cb.bytes.us.truncate(0);
+        self.cb.bytes.pw.truncate(0);
+        self.cb.auth(us, pw);
+        self
+    }
+
+    /// Use digest auth for every call.
+    pub fn digest_auth(&mut self, v: bool) -> &mut Self {
+        self.cb.digest_auth(v);
+        self
+    }
+
+    /// Base URL without username and password.
+    pub fn url(&self) -> String {
+        // Valid as it was parsed in new.
+        self.cb.to_url().map(|u| u.to_string()).unwrap_or_default()
+    }
+
+    /// CallBuilder for base URL with the given method.
+    /// Add to path with path_segm and to query with query.
+    pub fn call(&self, method: &str) -> Result<CallBuilder> {
+        let mut cb = self.cb.clone();
+        cb.method(method)?;
+        Ok(CallBuilder::from_impl(cb))
+    }
+
+    /// CallBuilder for reference resolved against base URL (RFC 3986).
+    /// Without a trailing / last segment of base path is replaced:
+    /// "users" against "https://host/v1" is "https://host/users",
+    /// against "https://host/v1/" it is "https://host/v1/users".
+    ///
+    /// Headers and auth are kept if resolved URL has the same scheme, host and port as base.
+    pub fn join(&self, method: &str, reference: &str) -> Result<CallBuilder> {
+        let mut cb = self.cb.clone();
+        cb.method(method)?;
+        let base = cb.to_url()?;
+        cb.join(reference)?;
+        if cb.to_url()?.origin() != base.origin() {
+            let mut other = CallBuilderImpl::new();
+            other.method = cb.method.clone();
+            other.tls = cb.tls;
+            other.port = cb.port;
+            other.bytes.host = cb.bytes.host.clone();
+            other.bytes.path = cb.bytes.path.clone();
+            other.bytes.query = cb.bytes.query.clone();
+            cb = other;
+        }
+        Ok(CallBuilder::from_impl(cb))
+    }
+
+    /// GET call for base URL.
+    pub fn get(&self) -> CallBuilder {
+        self.with(Method::GET, Vec::new())
+    }
+
+    /// POST call for base URL.
+    pub fn post(&self, body: Vec<u8>) -> CallBuilder {
+        self.with(Method::POST, body)
+    }
+
+    /// PUT call for base URL.
+    pub fn put(&self, body: Vec<u8>) -> CallBuilder {
+        self.with(Method::PUT, body)
+    }
+
+    /// PATCH call for base URL.
+    pub fn patch(&self, body: Vec<u8>) -> CallBuilder {
+        self.with(Method::PATCH, body)
+    }
+
+    /// DELETE call for base URL.
+    pub fn delete(&self) -> CallBuilder {
+        self.with(Method::DELETE, Vec::new())
+    }
+
+    fn with(&self, method: Method, body: Vec<u8>) -> CallBuilder {
+        let mut cb = self.cb.clone();
+        cb.method = method;
+        cb.body = body;
+        CallBuilder::from_impl(cb)
+    }
+}
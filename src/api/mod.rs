@@ -3,6 +3,7 @@ use std::fmt::{Display, Error as FmtError, Formatter};
 use std::fs;
 use std::io::Read;
 use std::str::from_utf8;
+use std::sync::Arc;
 
 /// Used when call is in send request state.
 #[derive(Debug)]
@@ -13,6 +14,11 @@ pub enum SendState {
     SentBody(usize),
     /// Waiting for body to be provided for sending.
     WaitReqBody,
+    /// Request was sent with Expect: 100-continue and body is held back until
+    /// server replies with 100 Continue or CallBuilder::expect_continue_ms passes
+    /// without a reply.
+    /// Call call_send again on next event or periodically (every 100ms for example).
+    WaitContinue,
     /// Call has switched to receiving state.
     Receiving,
     /// Request is done, body has been returned or
@@ -119,7 +125,13 @@ pub struct HttpcCfg {
     /// Extra root certificates in der format.
     pub der_ca: Vec<Vec<u8>>,
     /// Extra root certificates in pem format.
+    /// A single element may contain multiple certificates.
     pub pem_ca: Vec<Vec<u8>>,
+    /// Default: RootCerts::Bundled
+    ///
+    /// Which root certificates rustls trusts besides der_ca and pem_ca.
+    /// openssl and native backends always use the system store.
+    pub root_certs: RootCerts,
     /// Default: 8
     ///
     /// Max 8K buffers to keep cached for subsequent requests.
@@ -137,7 +149,113 @@ pub struct HttpcCfg {
     ///
     /// mio_httpc will check if public key of server matches any of the pins before
     /// sending the HTTP request.
+    ///
+    /// Every element is the same as an exact host PinSet in pin_sets.
     pub pins: Vec<(String, Vec<String>)>,
+    /// SSL Pinning with wildcard hosts, expiry and report-only mode.
+    /// Every pin set that applies to host must match.
+    pub pin_sets: Vec<PinSet>,
+    /// Called for every pin set that did not match, including report-only ones.
+    pub pin_report: Option<PinReport>,
+    /// Custom server certificate verification.
+    ///
+    /// If set, failed certificate verification of the TLS backend no longer aborts the handshake.
+    /// Its result is passed to the verifier which makes the final decision before
+    /// the HTTP request is sent.
+    ///
+    /// native backend can not defer its own verification. Verifier is only called for
+    /// certificates it accepted and the chain only contains the end-entity certificate.
+    pub verifier: Option<Arc<dyn CertVerifier>>,
+    /// Append TLS session secrets to this file in NSS key log format.
+    /// Used for decrypting captured traffic with Wireshark. Never enable it in production.
+    ///
+    /// Supported by rustls and openssl (1.1.1 or newer). Ignored by native.
+    pub key_log_file: Option<String>,
+    /// Headers added to every call, unless call sets a header with the same name
+    /// (or suppresses it with CallBuilder::suppress_header).
+    /// Authorization is not added if call has credentials (CallBuilder::auth or URL).
+    /// Calls fail with Error::InvalidDefaultHeader if an entry is not a valid header.
+    pub headers: Vec<(String, String)>,
+    /// Default CallBuilder::timeout_ms for every call.
+    pub timeout_ms: Option<u64>,
+    /// Default CallBuilder::max_redirects for every call.
+    pub max_redirects: Option<u8>,
+    /// Default CallBuilder::gzip for every call.
+    pub gzip: Option<bool>,
+    /// Default CallBuilder::max_response for every call.
+    pub max_response: Option<usize>,
+    /// Default CallBuilder::chunked_max_chunk for every call.
+    pub chunked_max_chunk: Option<usize>,
+}
+
+/// Custom server certificate verification. Set with HttpcCfg::verifier.
+///
+/// Any `Fn(&str, &[Vec<u8>], bool) -> Result<(), String>` closure is a CertVerifier.
+pub trait CertVerifier: Send + Sync {
+    /// host is the name the certificate was verified against.
+    /// chain is the certificate chain sent by server in der format, starting with the
+    /// end-entity certificate.
+    /// default_ok is the result of certificate verification done by TLS backend.
+    ///
+    /// Return an error with the reason to reject connection.
+    fn verify(
+        &self,
+        host: &str,
+        chain: &[Vec<u8>],
+        default_ok: bool,
+    ) -> ::std::result::Result<(), String>;
+}
+
+impl<F> CertVerifier for F
+where
+    F: Fn(&str, &[Vec<u8>], bool) -> ::std::result::Result<(), String> + Send + Sync,
+{
+    fn verify(
+        &self,
+        host: &str,
+        chain: &[Vec<u8>],
+        default_ok: bool,
+    ) -> ::std::result::Result<(), String> {
+        self(host, chain, default_ok)
+    }
+}
+
+/// Root certificate source for the rustls backend. Set with HttpcCfg::root_certs.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum RootCerts {
+    /// Mozilla root certificates compiled into the library (webpki-roots).
+    #[default]
+    Bundled,
+    /// Platform certificate store. If SSL_CERT_FILE is set, certificates are read
+    /// from that file instead. Certificates from files in SSL_CERT_DIR are added as well.
+    System,
+    /// Bundled and system certificates.
+    Both,
+}
+
+/// TLS protocol version for CallBuilder::tls_min_version and CallBuilder::tls_max_version.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TlsVersion {
+    Tls10,
+    Tls11,
+    Tls12,
+    Tls13,
+}
+
+/// Content-Encoding of request body set with CallBuilder::compress_body.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ContentEncoding {
+    Gzip,
+    Deflate,
+}
+
+impl ContentEncoding {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Deflate => "deflate",
+        }
+    }
 }
 
 impl HttpcCfg {
@@ -148,8 +266,16 @@ impl HttpcCfg {
         }
     }
 
+    /// Set key_log_file from SSLKEYLOGFILE environment variable if it is set.
+    pub fn key_log_from_env(&mut self) -> &mut Self {
+        if let Some(path) = ::std::env::var_os("SSLKEYLOGFILE") {
+            self.key_log_file = Some(path.to_string_lossy().into_owned());
+        }
+        self
+    }
+
     /// Will read pem files (extensions .crt or .pem) from path.
-    /// Path can be to file or folder.
+    /// Path can be to file or folder. Files may be bundles of multiple certificates.
     pub fn certs_from_path(path: &str) -> ::std::io::Result<HttpcCfg> {
         let mut cfg = HttpcCfg::new();
         let certs = [OsStr::new("crt"), OsStr::new("pem")];
@@ -168,13 +294,10 @@ impl HttpcCfg {
             let de = de.unwrap();
             match de.path().extension() {
                 Some(ex) if certs.contains(&ex) => {
-                    let meta = fs::metadata(de.path())?;
-                    if meta.len() < 1024 * 8 {
-                        let mut file = fs::File::open(de.path())?;
-                        let mut contents = Vec::new();
-                        file.read_to_end(&mut contents)?;
-                        cfg.pem_ca.push(contents);
-                    }
+                    let mut file = fs::File::open(de.path())?;
+                    let mut contents = Vec::new();
+                    file.read_to_end(&mut contents)?;
+                    cfg.pem_ca.push(contents);
                 }
                 _ => {}
             }
@@ -342,8 +465,24 @@ impl CallRef {
 mod websocket;
 pub use self::websocket::*;
 
+mod multipart;
+pub use self::multipart::Multipart;
+
+mod body;
+pub use self::body::BodySource;
+
+mod endpoint;
+pub use self::endpoint::Endpoint;
+
+mod sse;
+pub use self::sse::{EventSource, SseEvent};
+
 mod simple_call;
 pub use self::simple_call::*;
 
 mod builder;
 pub use self::builder::*;
+
+mod pins;
+pub(crate) use self::pins::check_pins;
+pub use self::pins::{PinFailure, PinReport, PinSet};
@@ -0,0 +1,144 @@
+use crate::types::{is_field_value, BodyPart};
+use data_encoding::HEXLOWER;
+use std::fs;
+use std::path::Path;
+
+/// multipart/form-data request body. Set with CallBuilder::multipart.
+///
+/// File parts are not loaded into memory, they are read from disk while request is sent.
+/// Content-Length uses file sizes read when parts are added with Multipart::file,
+/// so files must not change size before request is sent.
+#[derive(Debug, Clone)]
+pub struct Multipart {
+    boundary: String,
+    parts: Vec<BodyPart>,
+}
+
+impl Default for Multipart {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Quote and escape a Content-Disposition parameter like browsers do.
+fn escape(s: &str) -> String {
+    s.replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+// Content-Type is written as is, parameters may be quoted (charset="utf-8").
+fn check_content_type(s: &str) -> crate::Result<()> {
+    if s.is_empty() || !is_field_value(s) {
+        return Err(crate::Error::InvalidHeader);
+    }
+    Ok(())
+}
+
+impl Multipart {
+    /// New multipart body with a random boundary.
+    pub fn new() -> Multipart {
+        let rnd: [u8; 12] = ::rand::random();
+        Multipart {
+            boundary: format!("mio_httpc-{}", HEXLOWER.encode(&rnd)),
+            parts: Vec::new(),
+        }
+    }
+
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+
+    /// Content-Type header value.
+    pub fn content_type(&self) -> String {
+        format!("multipart/form-data; boundary={}", self.boundary)
+    }
+
+    /// Size of entire body in bytes.
+    pub fn content_len(&self) -> u64 {
+        self.parts.iter().filter_map(|p| p.len()).sum::<u64>() + self.boundary.len() as u64 + 6
+    }
+
+    /// Add text field.
+    pub fn text(&mut self, name: &str, value: &str) -> &mut Self {
+        self.part_header(name, None, None);
+        self.push(value.as_bytes());
+        self.push(b"\r\n");
+        self
+    }
+
+    /// Add data part. If filename is set it is sent as a file.
+    /// Returns Error::InvalidHeader if content_type is not a valid header value.
+    pub fn bytes(
+        &mut self,
+        name: &str,
+        filename: Option<&str>,
+        content_type: &str,
+        data: &[u8],
+    ) -> crate::Result<&mut Self> {
+        check_content_type(content_type)?;
+        self.part_header(name, filename, Some(content_type));
+        self.push(data);
+        self.push(b"\r\n");
+        Ok(self)
+    }
+
+    /// Add file part. It is read from disk when request is sent.
+    /// If filename is None file name from path is used.
+    /// Returns Error::InvalidHeader if content_type is not a valid header value.
+    pub fn file<P: AsRef<Path>>(
+        &mut self,
+        name: &str,
+        path: P,
+        filename: Option<&str>,
+        content_type: &str,
+    ) -> crate::Result<&mut Self> {
+        check_content_type(content_type)?;
+        let path = path.as_ref();
+        let meta = fs::metadata(path)?;
+        if !meta.is_file() {
+            return Err(crate::Error::Other("Multipart file part is not a file"));
+        }
+        let default_name = path.file_name().map(|n| n.to_string_lossy());
+        let filename = filename.or(default_name.as_deref()).unwrap_or("");
+        self.part_header(name, Some(filename), Some(content_type));
+        self.parts
+            .push(BodyPart::File(path.to_path_buf(), meta.len()));
+        self.push(b"\r\n");
+        Ok(self)
+    }
+
+    fn part_header(&mut self, name: &str, filename: Option<&str>, content_type: Option<&str>) {
+        let mut hdr = format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"{}\"",
+            self.boundary,
+            escape(name)
+        );
+        if let Some(filename) = filename {
+            hdr.push_str("; filename=\"");
+            hdr.push_str(&escape(filename));
+            hdr.push('"');
+        }
+        if let Some(content_type) = content_type {
+            hdr.push_str("\r\nContent-Type: ");
+            hdr.push_str(content_type);
+        }
+        hdr.push_str("\r\n\r\n");
+        self.push(hdr.as_bytes());
+    }
+
+    // Append to last bytes part.
+    fn push(&mut self, b: &[u8]) {
+        if let Some(BodyPart::Bytes(ref mut last)) = self.parts.last_mut() {
+            last.extend_from_slice(b);
+            return;
+        }
+        self.parts.push(BodyPart::Bytes(b.to_vec()));
+    }
+
+    pub(crate) fn finish(mut self) -> Vec<BodyPart> {
+        let end = format!("--{}--\r\n", self.boundary);
+        self.push(end.as_bytes());
+        self.parts
+    }
+}
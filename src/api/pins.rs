@@ -0,0 +1,269 @@
+use crate::tls_api::{hash, HashType};
+use crate::HttpcCfg;
+use data_encoding::BASE64;
+use std::fmt;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Set of pins for a host. Added to HttpcCfg::pin_sets.
+///
+/// A pin is formatted as: sha256/base64(subjectPublicKeyInfo)
+/// sha1 is also supported though not recommended.
+///
+/// Pins are compared against every certificate in the chain sent by server,
+/// so pinning an intermediate or root certificate is possible.
+#[derive(Debug, Clone, Default)]
+pub struct PinSet {
+    /// Hostname or wildcard like *.example.com which matches a single subdomain level.
+    pub host: String,
+    /// Also match every subdomain of host.
+    pub include_subdomains: bool,
+    pub pins: Vec<String>,
+    /// Pin set is ignored after this time.
+    pub expires: Option<SystemTime>,
+    /// Do not fail connection on mismatch, only call HttpcCfg::pin_report.
+    pub report_only: bool,
+}
+
+impl PinSet {
+    pub fn new(host: &str, pins: &[&str]) -> PinSet {
+        PinSet {
+            host: host.to_string(),
+            pins: pins.iter().map(|p| p.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn include_subdomains(mut self) -> PinSet {
+        self.include_subdomains = true;
+        self
+    }
+
+    pub fn expires(mut self, t: SystemTime) -> PinSet {
+        self.expires = Some(t);
+        self
+    }
+
+    pub fn report_only(mut self) -> PinSet {
+        self.report_only = true;
+        self
+    }
+
+    /// Does pin set apply to host.
+    pub fn matches_host(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.');
+        let pattern = self.host.trim_end_matches('.');
+        if host.eq_ignore_ascii_case(pattern) {
+            return true;
+        }
+        if let Some(parent) = pattern.strip_prefix("*.") {
+            if let Some(pos) = host.find('.') {
+                if pos > 0 && host[pos + 1..].eq_ignore_ascii_case(parent) {
+                    return true;
+                }
+            }
+        }
+        if self.include_subdomains {
+            let parent = pattern.trim_start_matches("*.");
+            if host.len() > parent.len() + 1 {
+                let (sub, rest) = host.split_at(host.len() - parent.len());
+                return sub.ends_with('.') && rest.eq_ignore_ascii_case(parent);
+            }
+        }
+        false
+    }
+
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        match self.expires {
+            Some(t) => t <= now,
+            None => false,
+        }
+    }
+
+    fn matches_spki(&self, spkis: &[&[u8]]) -> bool {
+        for pin in self.pins.iter() {
+            let (algo, prefix) = if pin.starts_with("sha256/") {
+                (HashType::SHA256, "sha256/")
+            } else if pin.starts_with("sha1/") {
+                (HashType::SHA1, "sha1/")
+            } else {
+                continue;
+            };
+            for spki in spkis.iter() {
+                let hashed = BASE64.encode(&hash(algo, spki));
+                if hashed == pin[prefix.len()..] {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+/// Callback for HttpcCfg::pin_report.
+pub type PinReport = Arc<dyn Fn(&PinFailure) + Send + Sync>;
+
+/// Details of a pin mismatch. Passed to HttpcCfg::pin_report and returned in Error::InvalidPin.
+#[derive(Debug, Clone)]
+pub struct PinFailure {
+    /// Host that was connected to.
+    pub host: String,
+    /// Host of pin set that did not match.
+    pub pin_host: String,
+    /// Pins of the pin set.
+    pub pins: Vec<String>,
+    /// sha256/base64(subjectPublicKeyInfo) of every certificate in the chain sent by server.
+    pub observed: Vec<String>,
+    /// Connection was allowed to continue.
+    pub report_only: bool,
+}
+
+impl fmt::Display for PinFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "host={} observed=[{}]",
+            self.host,
+            self.observed.join(", ")
+        )
+    }
+}
+
+/// Check all pin sets for host. Calls HttpcCfg::pin_report for every mismatch.
+pub(crate) fn check_pins(
+    cfg: &HttpcCfg,
+    host: &str,
+    chain: &[Vec<u8>],
+    leaf_spki: &[u8],
+) -> crate::Result<()> {
+    let now = SystemTime::now();
+    let legacy: Vec<PinSet> = cfg
+        .pins
+        .iter()
+        .filter(|p| p.0.eq_ignore_ascii_case(host))
+        .map(|p| PinSet {
+            host: p.0.clone(),
+            pins: p.1.clone(),
+            ..Default::default()
+        })
+        .collect();
+    let mut spkis: Vec<&[u8]> = chain.iter().filter_map(|c| cert_spki(c)).collect();
+    if spkis.is_empty() && !leaf_spki.is_empty() {
+        spkis.push(leaf_spki);
+    }
+    for set in legacy.iter().chain(cfg.pin_sets.iter()) {
+        if !set.matches_host(host) || set.is_expired(now) || set.matches_spki(&spkis) {
+            continue;
+        }
+        let failure = PinFailure {
+            host: host.to_string(),
+            pin_host: set.host.clone(),
+            pins: set.pins.clone(),
+            observed: spkis
+                .iter()
+                .map(|spki| format!("sha256/{}", BASE64.encode(&hash(HashType::SHA256, spki))))
+                .collect(),
+            report_only: set.report_only,
+        };
+        if let Some(ref report) = cfg.pin_report {
+            report(&failure);
+        }
+        if !set.report_only {
+            return Err(crate::Error::InvalidPin(failure));
+        }
+    }
+    Ok(())
+}
+
+// Returns (tag, content, rest) of the first DER element.
+fn der_read(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    if data.len() < 2 {
+        return None;
+    }
+    let tag = data[0];
+    let (len, off) = if data[1] & 0x80 == 0 {
+        (data[1] as usize, 2)
+    } else {
+        let n = (data[1] & 0x7f) as usize;
+        if n == 0 || n > 4 || data.len() < 2 + n {
+            return None;
+        }
+        let mut len = 0usize;
+        for b in data[2..2 + n].iter() {
+            len = (len << 8) | *b as usize;
+        }
+        (len, 2 + n)
+    };
+    if data.len() - off < len {
+        return None;
+    }
+    Some((tag, &data[off..off + len], &data[off + len..]))
+}
+
+/// subjectPublicKeyInfo of a der certificate.
+fn cert_spki(cert: &[u8]) -> Option<&[u8]> {
+    let (_, cert, _) = der_read(cert)?;
+    let (_, tbs, _) = der_read(cert)?;
+    let mut rest = tbs;
+    // version is optional
+    if rest.first() == Some(&0xa0) {
+        rest = der_read(rest)?.2;
+    }
+    // serial, signature, issuer, validity, subject
+    for _ in 0..5 {
+        rest = der_read(rest)?.2;
+    }
+    let (_, _, after) = der_read(rest)?;
+    Some(&rest[..rest.len() - after.len()])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn pin_host_match() {
+        let exact = PinSet::new("example.com", &[]);
+        assert!(exact.matches_host("example.com"));
+        assert!(exact.matches_host("EXAMPLE.com."));
+        assert!(!exact.matches_host("www.example.com"));
+
+        let wild = PinSet::new("*.example.com", &[]);
+        assert!(wild.matches_host("www.example.com"));
+        assert!(!wild.matches_host("example.com"));
+        assert!(!wild.matches_host("a.b.example.com"));
+        assert!(!wild.matches_host("wwwexample.com"));
+
+        let sub = PinSet::new("example.com", &[]).include_subdomains();
+        assert!(sub.matches_host("example.com"));
+        assert!(sub.matches_host("a.b.example.com"));
+        assert!(!sub.matches_host("badexample.com"));
+    }
+
+    #[test]
+    fn pin_expiry() {
+        let now = SystemTime::now();
+        let set = PinSet::new("example.com", &[]);
+        assert!(!set.is_expired(now));
+        let set = set.expires(now - Duration::from_secs(1));
+        assert!(set.is_expired(now));
+        let set = PinSet::new("example.com", &[]).expires(now + Duration::from_secs(60));
+        assert!(!set.is_expired(now));
+    }
+
+    #[test]
+    fn pin_cert_spki() {
+        let spki = [0x30, 0x03, 0x02, 0x01, 0x07];
+        let mut tbs = vec![0xa0, 0x03, 0x02, 0x01, 0x02];
+        for _ in 0..5 {
+            tbs.extend_from_slice(&[0x30, 0x00]);
+        }
+        tbs.extend_from_slice(&spki);
+        tbs.extend_from_slice(&[0xa3, 0x00]);
+        let mut cert = vec![0x30, tbs.len() as u8 + 2, 0x30, tbs.len() as u8];
+        cert.extend_from_slice(&tbs);
+        assert_eq!(cert_spki(&cert), Some(&spki[..]));
+        assert_eq!(cert_spki(&cert[..10]), None);
+    }
+}
@@ -94,7 +94,7 @@ impl SimpleCall {
         }
         if self.state == State::Sending {
             match htp.call_send(poll, &mut self.id, None) {
-                SendState::Wait => {}
+                SendState::Wait | SendState::WaitContinue => {}
                 SendState::Receiving => {
                     self.state = State::Receiving;
                 }
@@ -0,0 +1,403 @@
+use crate::types::CallBuilderImpl;
+use crate::{Call, CallRef, Httpc, RecvState, Response, SendState};
+use mio::Registry;
+use std::time::{Duration, Instant};
+
+/// Event received from EventSource.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SseEvent {
+    /// Event type. "message" if server did not set one.
+    pub event: String,
+    /// Data lines joined with "\n".
+    pub data: String,
+    /// Last event ID at the time event was received.
+    pub id: String,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum State {
+    Sending,
+    Receiving,
+    Open,
+    Reconnect(Instant),
+    Closed,
+}
+
+// text/event-stream line parser.
+struct Parser {
+    event: String,
+    data: String,
+    id: String,
+    last_id: String,
+    retry: Duration,
+}
+
+impl Parser {
+    fn new() -> Parser {
+        Parser {
+            event: String::new(),
+            data: String::new(),
+            id: String::new(),
+            last_id: String::new(),
+            retry: Duration::from_secs(3),
+        }
+    }
+
+    // Incomplete event is discarded when stream ends.
+    fn reset_stream(&mut self) {
+        self.event.clear();
+        self.data.clear();
+        self.id = self.last_id.clone();
+    }
+
+    fn line(&mut self, line: &str) -> Option<SseEvent> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        if line.starts_with(':') {
+            return None;
+        }
+        let (field, value) = match line.find(':') {
+            Some(i) => {
+                let v = &line[i + 1..];
+                (&line[..i], v.strip_prefix(' ').unwrap_or(v))
+            }
+            None => (line, ""),
+        };
+        match field {
+            "event" => {
+                self.event.clear();
+                self.event.push_str(value);
+            }
+            "data" => {
+                self.data.push_str(value);
+                self.data.push('\n');
+            }
+            "id" if !value.contains('\0') => {
+                self.id.clear();
+                self.id.push_str(value);
+            }
+            "retry" if !value.is_empty() && value.bytes().all(|c| c.is_ascii_digit()) => {
+                if let Ok(ms) = value.parse() {
+                    self.retry = Duration::from_millis(ms);
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        self.last_id.clone_from(&self.id);
+        if self.data.is_empty() {
+            self.event.clear();
+            return None;
+        }
+        self.data.pop();
+        let event = if self.event.is_empty() {
+            "message".to_string()
+        } else {
+            ::std::mem::take(&mut self.event)
+        };
+        Some(SseEvent {
+            event,
+            data: ::std::mem::take(&mut self.data),
+            id: self.last_id.clone(),
+        })
+    }
+}
+
+// Returns (line length, length including line terminator).
+// None if no complete line or \r is last byte as \n may follow.
+fn find_eol(b: &[u8]) -> Option<(usize, usize)> {
+    let i = b.iter().position(|c| *c == b'\n' || *c == b'\r')?;
+    if b[i] == b'\n' {
+        Some((i, i + 1))
+    } else if i + 1 == b.len() {
+        None
+    } else if b[i + 1] == b'\n' {
+        Some((i, i + 2))
+    } else {
+        Some((i, i + 1))
+    }
+}
+
+/// Server-Sent Events client (text/event-stream).
+///
+/// Started with CallBuilder::event_source. Request is a GET with Accept: text/event-stream.
+/// If connection ends or fails with an IO error, EventSource reconnects after retry
+/// interval (3s or as set by server) and sends Last-Event-ID if server set an event id.
+///
+/// Call recv_event in a loop until it returns None, whenever httpc::event returns this call
+/// and periodically (every 100ms for example) so reconnects can happen.
+pub struct EventSource {
+    id: Call,
+    state: State,
+    // Request used for reconnecting.
+    cb: CallBuilderImpl,
+    // Received body, parsed from pos.
+    buf: Vec<u8>,
+    pos: usize,
+    bom_checked: bool,
+    max_line: usize,
+    parser: Parser,
+}
+
+impl EventSource {
+    pub(crate) fn new(id: Call, cb: CallBuilderImpl) -> EventSource {
+        EventSource {
+            id,
+            state: State::Sending,
+            max_line: cb.max_response,
+            cb,
+            buf: Vec::new(),
+            pos: 0,
+            bom_checked: false,
+            parser: Parser::new(),
+        }
+    }
+
+    pub fn call(&self) -> &Call {
+        &self.id
+    }
+
+    /// True if connected and receiving events.
+    pub fn is_open(&self) -> bool {
+        self.state == State::Open
+    }
+
+    /// True if closed and recv_event returns Error::Closed.
+    pub fn is_closed(&self) -> bool {
+        self.state == State::Closed
+    }
+
+    /// ID of last received event. Sent as Last-Event-ID when reconnecting if not empty.
+    pub fn last_event_id(&self) -> &str {
+        &self.parser.last_id
+    }
+
+    /// Reconnect delay.
+    pub fn retry(&self) -> Duration {
+        self.parser.retry
+    }
+
+    pub fn is_ref(&self, r: CallRef) -> bool {
+        self.id.is_ref(r)
+    }
+
+    /// For quick comparison with httpc::event response.
+    /// If cid is none will return false.
+    pub fn is_call(&self, cid: &Option<CallRef>) -> bool {
+        if let Some(ref b) = *cid {
+            return self.id.is_ref(*b);
+        }
+        false
+    }
+
+    /// Close connection.
+    pub fn finish(mut self, htp: &mut Httpc) {
+        self.close(htp);
+    }
+
+    fn close(&mut self, htp: &mut Httpc) {
+        self.state = State::Closed;
+        htp.call_close(::std::mem::replace(&mut self.id, Call::empty()));
+    }
+
+    /// You should call this in a loop until you get None.
+    ///
+    /// Returns Error::Closed if server replied with 204 (no more reconnecting)
+    /// and Error::EventSourceFail if response was not 200 with text/event-stream.
+    pub fn recv_event(
+        &mut self,
+        htp: &mut Httpc,
+        poll: &Registry,
+    ) -> crate::Result<Option<SseEvent>> {
+        loop {
+            match self.next_event() {
+                Ok(Some(ev)) => return Ok(Some(ev)),
+                Ok(None) => {}
+                Err(e) => {
+                    self.close(htp);
+                    return Err(e);
+                }
+            }
+            match self.state {
+                State::Closed => return Err(crate::Error::Closed),
+                State::Reconnect(at) => {
+                    if Instant::now() < at {
+                        return Ok(None);
+                    }
+                    self.connect(htp, poll)?;
+                }
+                State::Sending => match htp.call_send(poll, &mut self.id, None) {
+                    SendState::Receiving => {
+                        self.state = State::Receiving;
+                    }
+                    SendState::Wait | SendState::WaitContinue | SendState::SentBody(_) => {
+                        return Ok(None)
+                    }
+                    SendState::Done => self.reconnect(htp),
+                    SendState::WaitReqBody => {
+                        self.close(htp);
+                        return Err(crate::Error::MissingBody);
+                    }
+                    SendState::Error(e) => self.failed(htp, e)?,
+                },
+                State::Receiving | State::Open => {
+                    match htp.call_recv(poll, &mut self.id, Some(&mut self.buf)) {
+                        RecvState::Response(resp, _) => self.opened(htp, resp)?,
+                        RecvState::ReceivedBody(_) => {}
+                        RecvState::DoneWithBody(body) => {
+                            self.buf.extend_from_slice(&body);
+                            self.reconnect(htp);
+                        }
+                        RecvState::Done => self.reconnect(htp),
+                        RecvState::Sending => {
+                            self.state = State::Sending;
+                        }
+                        RecvState::Wait => return Ok(None),
+                        RecvState::Error(e) => self.failed(htp, e)?,
+                    }
+                }
+            }
+        }
+    }
+
+    fn connect(&mut self, htp: &mut Httpc, poll: &Registry) -> crate::Result<()> {
+        let mut cb = self.cb.clone();
+        if !self.parser.last_id.is_empty() {
+            cb.header("Last-Event-ID", &self.parser.last_id);
+        }
+        self.buf.clear();
+        self.pos = 0;
+        self.bom_checked = false;
+        self.parser.reset_stream();
+        match htp.start_call(cb, poll) {
+            Ok(id) => {
+                self.id = id;
+                self.state = State::Sending;
+                Ok(())
+            }
+            Err(e) => {
+                self.state = State::Closed;
+                Err(e)
+            }
+        }
+    }
+
+    fn reconnect(&mut self, htp: &mut Httpc) {
+        htp.call_close(::std::mem::replace(&mut self.id, Call::empty()));
+        self.state = State::Reconnect(Instant::now() + self.parser.retry);
+    }
+
+    // Network errors cause a reconnect, anything else closes.
+    fn failed(&mut self, htp: &mut Httpc, e: crate::Error) -> crate::Result<()> {
+        match e {
+            crate::Error::Io(_) | crate::Error::Closed => {
+                self.reconnect(htp);
+                Ok(())
+            }
+            e => {
+                self.close(htp);
+                Err(e)
+            }
+        }
+    }
+
+    fn opened(&mut self, htp: &mut Httpc, resp: Response) -> crate::Result<()> {
+        let event_stream = resp.headers().any(|h| {
+            h.name.eq_ignore_ascii_case("content-type")
+                && h.value.trim_start().starts_with("text/event-stream")
+        });
+        if resp.status == 200 && event_stream {
+            self.state = State::Open;
+            return Ok(());
+        }
+        self.close(htp);
+        if resp.status == 204 {
+            return Err(crate::Error::Closed);
+        }
+        Err(crate::Error::EventSourceFail(resp))
+    }
+
+    fn next_event(&mut self) -> crate::Result<Option<SseEvent>> {
+        if !self.bom_checked {
+            if self.buf.len() < 3 && b"\xef\xbb\xbf".starts_with(&self.buf) {
+                return Ok(None);
+            }
+            if self.buf.starts_with(b"\xef\xbb\xbf") {
+                self.pos = 3;
+            }
+            self.bom_checked = true;
+        }
+        while let Some((len, next)) = find_eol(&self.buf[self.pos..]) {
+            let line = String::from_utf8_lossy(&self.buf[self.pos..self.pos + len]);
+            self.pos += next;
+            if let Some(ev) = self.parser.line(&line) {
+                return Ok(Some(ev));
+            }
+        }
+        if self.buf.len() - self.pos > self.max_line {
+            return Err(crate::Error::ResponseTooBig);
+        }
+        self.buf.drain(..self.pos);
+        self.pos = 0;
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Vec<SseEvent> {
+        let mut p = Parser::new();
+        let mut out = Vec::new();
+        let mut b = s.as_bytes();
+        while let Some((len, next)) = find_eol(b) {
+            out.extend(p.line(std::str::from_utf8(&b[..len]).unwrap()));
+            b = &b[next..];
+        }
+        out
+    }
+
+    fn ev(event: &str, data: &str, id: &str) -> SseEvent {
+        SseEvent {
+            event: event.to_string(),
+            data: data.to_string(),
+            id: id.to_string(),
+        }
+    }
+
+    #[test]
+    fn sse_parse() {
+        assert_eq!(
+            parse("data: a\ndata:b\n\n: comment\nevent: add\ndata\nid: 7\n\n"),
+            vec![ev("message", "a\nb", ""), ev("add", "", "7")]
+        );
+        // CR, CRLF line endings, no data does not dispatch but keeps id.
+        assert_eq!(
+            parse("id: 1\r\rdata:  x\r\n\r\n"),
+            vec![ev("message", " x", "1")]
+        );
+        // Unfinished event is not dispatched.
+        assert_eq!(parse("data: a\n"), vec![]);
+        // Id with NUL is ignored.
+        assert_eq!(
+            parse("id: 2\nid: a\0\ndata\n\n"),
+            vec![ev("message", "", "2")]
+        );
+        assert_eq!(find_eol(b"a\r"), None);
+    }
+
+    #[test]
+    fn sse_retry() {
+        let mut p = Parser::new();
+        p.line("retry: 150");
+        assert_eq!(p.retry, Duration::from_millis(150));
+        p.line("retry: 1s");
+        p.line("retry:");
+        assert_eq!(p.retry, Duration::from_millis(150));
+    }
+}
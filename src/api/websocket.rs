@@ -1,6 +1,376 @@
+use crate::tls_api::{hash, HashType};
+use crate::types::WsOpts;
 use crate::{Call, CallRef, Httpc, RecvState, ResponseBody, SendState};
 use byteorder::{BigEndian, ByteOrder};
+use data_encoding::BASE64;
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
 use mio::Registry;
+use std::fmt;
+use std::io::IoSlice;
+use std::time::{Duration, Instant};
+
+const WS_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Why WebSocket setup failed. Returned in Error::WebSocketFail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WsFail {
+    /// Response status was not 101.
+    Status,
+    /// Response is missing Upgrade: websocket.
+    Upgrade,
+    /// Response is missing Connection: upgrade.
+    Connection,
+    /// Sec-WebSocket-Accept is missing or does not match the key that was sent.
+    Accept,
+    /// Server selected an extension that was not offered or sent invalid extension parameters.
+    Extension,
+    /// Server selected a subprotocol that was not offered.
+    Protocol,
+}
+
+impl fmt::Display for WsFail {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match *self {
+            WsFail::Status => "status is not 101",
+            WsFail::Upgrade => "missing Upgrade: websocket",
+            WsFail::Connection => "missing Connection: upgrade",
+            WsFail::Accept => "invalid Sec-WebSocket-Accept",
+            WsFail::Extension => "unexpected extension",
+            WsFail::Protocol => "unexpected subprotocol",
+        };
+        f.write_str(s)
+    }
+}
+
+// Check handshake response headers (RFC 6455 4.1). Returns selected subprotocol.
+fn check_handshake(opts: &WsOpts, resp: &crate::Response) -> Result<Option<String>, WsFail> {
+    if resp.status != 101 {
+        return Err(WsFail::Status);
+    }
+    if !resp.ws {
+        return Err(WsFail::Upgrade);
+    }
+    let mut connection = false;
+    let mut accept = false;
+    let mut protocol = None;
+    let expect = BASE64.encode(&hash(
+        HashType::SHA1,
+        format!("{}{}", opts.key, WS_GUID).as_bytes(),
+    ));
+    for h in resp.headers() {
+        if h.is("connection") {
+            if h.value
+                .split(',')
+                .any(|v| v.trim().eq_ignore_ascii_case("upgrade"))
+            {
+                connection = true;
+            }
+        } else if h.is("sec-websocket-accept") {
+            if accept || h.value.trim() != expect {
+                return Err(WsFail::Accept);
+            }
+            accept = true;
+        } else if h.is("sec-websocket-protocol") {
+            // Server must select at most one of the offered subprotocols.
+            let v = h.value.trim();
+            if protocol.is_some() || !opts.protocols.iter().any(|p| p == v) {
+                return Err(WsFail::Protocol);
+            }
+            protocol = Some(v.to_string());
+        }
+    }
+    if !connection {
+        return Err(WsFail::Connection);
+    }
+    if !accept {
+        return Err(WsFail::Accept);
+    }
+    Ok(protocol)
+}
+
+/// WebSocket close status code (RFC 6455 7.4).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseCode {
+    /// 1000
+    Normal,
+    /// 1001
+    GoingAway,
+    /// 1002
+    Protocol,
+    /// 1003
+    Unsupported,
+    /// 1005, close did not contain a status code. Never sent.
+    NoStatus,
+    /// 1006, connection closed without a close handshake. Never sent.
+    Abnormal,
+    /// 1007, invalid UTF-8 in text message.
+    InvalidData,
+    /// 1008
+    Policy,
+    /// 1009
+    TooBig,
+    /// 1010
+    MandatoryExt,
+    /// 1011
+    Internal,
+    /// Any other code.
+    Other(u16),
+}
+
+impl CloseCode {
+    /// Can code be sent in a close packet.
+    pub fn is_valid(&self) -> bool {
+        matches!(u16::from(*self), 1000..=1003 | 1007..=1014 | 3000..=4999)
+    }
+}
+
+impl From<u16> for CloseCode {
+    fn from(v: u16) -> CloseCode {
+        match v {
+            1000 => CloseCode::Normal,
+            1001 => CloseCode::GoingAway,
+            1002 => CloseCode::Protocol,
+            1003 => CloseCode::Unsupported,
+            1005 => CloseCode::NoStatus,
+            1006 => CloseCode::Abnormal,
+            1007 => CloseCode::InvalidData,
+            1008 => CloseCode::Policy,
+            1009 => CloseCode::TooBig,
+            1010 => CloseCode::MandatoryExt,
+            1011 => CloseCode::Internal,
+            _ => CloseCode::Other(v),
+        }
+    }
+}
+
+impl From<CloseCode> for u16 {
+    fn from(v: CloseCode) -> u16 {
+        match v {
+            CloseCode::Normal => 1000,
+            CloseCode::GoingAway => 1001,
+            CloseCode::Protocol => 1002,
+            CloseCode::Unsupported => 1003,
+            CloseCode::NoStatus => 1005,
+            CloseCode::Abnormal => 1006,
+            CloseCode::InvalidData => 1007,
+            CloseCode::Policy => 1008,
+            CloseCode::TooBig => 1009,
+            CloseCode::MandatoryExt => 1010,
+            CloseCode::Internal => 1011,
+            CloseCode::Other(v) => v,
+        }
+    }
+}
+
+// Parse received close payload. On error returns code to close with.
+fn parse_close(payload: &[u8]) -> Result<(CloseCode, String), (CloseCode, &'static str)> {
+    if payload.is_empty() {
+        return Ok((CloseCode::NoStatus, String::new()));
+    }
+    if payload.len() < 2 {
+        return Err((CloseCode::Protocol, "close payload of 1 byte"));
+    }
+    let code = CloseCode::from(BigEndian::read_u16(payload));
+    if !code.is_valid() {
+        return Err((CloseCode::Protocol, "invalid close code"));
+    }
+    match ::std::str::from_utf8(&payload[2..]) {
+        Ok(reason) => Ok((code, reason.to_string())),
+        Err(_) => Err((CloseCode::InvalidData, "invalid UTF-8 in close reason")),
+    }
+}
+
+/// permessage-deflate settings (RFC 7692). Set with CallBuilder::ws_deflate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WsDeflate {
+    /// Default true.
+    ///
+    /// Compress outgoing text and binary messages. send_bin_inplace is never compressed.
+    pub compress: bool,
+    /// Default false.
+    ///
+    /// Reset compression context after every sent message.
+    /// Uses less memory on server, compresses worse.
+    pub client_no_context_takeover: bool,
+    /// Default false.
+    ///
+    /// Ask server to reset its compression context after every message.
+    pub server_no_context_takeover: bool,
+    /// Default none.
+    ///
+    /// Ask server to limit its LZ77 window size (8-15).
+    pub server_max_window_bits: Option<u8>,
+}
+
+impl Default for WsDeflate {
+    fn default() -> WsDeflate {
+        WsDeflate {
+            compress: true,
+            client_no_context_takeover: false,
+            server_no_context_takeover: false,
+            server_max_window_bits: None,
+        }
+    }
+}
+
+// Negotiated permessage-deflate.
+struct Deflate {
+    compress: Option<Compress>,
+    decompress: Decompress,
+    client_no_context_takeover: bool,
+    server_no_context_takeover: bool,
+    // Outgoing message is compressed and in the middle of fragments.
+    send_compressed: bool,
+    // Received message is compressed and in the middle of fragments.
+    recv_compressed: bool,
+}
+
+const DEFLATE_TRAILER: [u8; 4] = [0, 0, 0xff, 0xff];
+
+impl Deflate {
+    fn new(opts: &WsDeflate, client_no_ctx: bool, server_no_ctx: bool) -> Deflate {
+        Deflate {
+            compress: if opts.compress {
+                Some(Compress::new(Compression::default(), false))
+            } else {
+                None
+            },
+            decompress: Decompress::new(false),
+            client_no_context_takeover: client_no_ctx,
+            server_no_context_takeover: server_no_ctx,
+            send_compressed: false,
+            recv_compressed: false,
+        }
+    }
+
+    // Parse Sec-WebSocket-Extensions response. Returns None if server response is invalid.
+    fn negotiate(opts: Option<&WsDeflate>, resp: &crate::Response) -> Option<Option<Deflate>> {
+        let mut out = None;
+        for h in resp.headers() {
+            if !h.is("sec-websocket-extensions") {
+                continue;
+            }
+            for ext in h.value.split(',') {
+                let mut params = ext.split(';').map(|p| p.trim());
+                let name = params.next()?;
+                if name.is_empty() {
+                    continue;
+                }
+                // We only offer permessage-deflate and only once.
+                let opts = opts?;
+                if !name.eq_ignore_ascii_case("permessage-deflate") || out.is_some() {
+                    return None;
+                }
+                let mut client_no_ctx = opts.client_no_context_takeover;
+                let mut server_no_ctx = false;
+                for param in params {
+                    let mut kv = param.splitn(2, '=');
+                    let k = kv.next().unwrap_or("").trim();
+                    let v = kv.next().map(|v| v.trim().trim_matches('"'));
+                    match (k, v) {
+                        ("client_no_context_takeover", None) => client_no_ctx = true,
+                        ("server_no_context_takeover", None) => server_no_ctx = true,
+                        ("server_max_window_bits", Some(v)) => match v.parse::<u8>() {
+                            Ok(bits) if (8..=15).contains(&bits) => {}
+                            _ => return None,
+                        },
+                        // client_max_window_bits was not offered, we can not limit our window.
+                        _ => return None,
+                    }
+                }
+                out = Some(Deflate::new(opts, client_no_ctx, server_no_ctx));
+            }
+        }
+        Some(out)
+    }
+
+    // Compress a message fragment into out.
+    fn deflate(&mut self, fin: bool, pkt: &[u8], out: &mut Vec<u8>) -> crate::Result<()> {
+        let c = match self.compress {
+            Some(ref mut c) => c,
+            None => return Ok(()),
+        };
+        let mut input = pkt;
+        loop {
+            out.reserve(input.len() / 2 + 64);
+            let before = c.total_in();
+            c.compress_vec(input, out, FlushCompress::Sync)
+                .map_err(|_| crate::Error::DecompressionFailure)?;
+            input = &input[(c.total_in() - before) as usize..];
+            if input.is_empty() && out.len() < out.capacity() {
+                break;
+            }
+        }
+        if fin {
+            if out.ends_with(&DEFLATE_TRAILER) {
+                let len = out.len();
+                out.truncate(len - DEFLATE_TRAILER.len());
+            }
+            if self.client_no_context_takeover {
+                c.reset();
+            }
+        }
+        Ok(())
+    }
+
+    // Decompress a received frame into out.
+    fn inflate(
+        &mut self,
+        fin: bool,
+        pkt: &[u8],
+        max: usize,
+        out: &mut Vec<u8>,
+    ) -> crate::Result<()> {
+        let d = &mut self.decompress;
+        for mut input in [pkt, if fin { &DEFLATE_TRAILER[..] } else { &[] }] {
+            loop {
+                if out.len() > max {
+                    return Err(crate::Error::ResponseTooBig);
+                }
+                out.reserve(input.len() * 2 + 1024);
+                let before = d.total_in();
+                let st = d
+                    .decompress_vec(input, out, FlushDecompress::Sync)
+                    .map_err(|_| crate::Error::DecompressionFailure)?;
+                input = &input[(d.total_in() - before) as usize..];
+                if st == Status::StreamEnd || (input.is_empty() && out.len() < out.capacity()) {
+                    break;
+                }
+            }
+        }
+        if fin && self.server_no_context_takeover {
+            d.reset(false);
+        }
+        Ok(())
+    }
+}
+
+// (fin, rsv1, opcode, payload offset, payload length)
+type FrameHdr = (bool, bool, u8, usize, usize);
+
+// Received frame. Payload is at pos..pos+len of received data starting at base,
+// or in Httpc ws_buf if buffered.
+struct Frame {
+    fin: bool,
+    // Message opcode, continuation frames get the opcode of their message.
+    op: u8,
+    cont: bool,
+    base: usize,
+    pos: usize,
+    len: usize,
+    buffered: bool,
+}
+
+impl Frame {
+    fn data<'a>(&self, htp: &'a mut Httpc, id: &Call) -> &'a [u8] {
+        if self.buffered {
+            return htp.ws_buf();
+        }
+        // base was already adjusted by peek_body so this returns the same slice.
+        let mut off = self.base;
+        &htp.peek_body(id, &mut off)[self.pos..self.pos + self.len]
+    }
+}
 
 /// WebSocket packet received from server.
 pub enum WSPacket<'a> {
@@ -17,7 +387,7 @@ pub enum WSPacket<'a> {
     Pong(&'a [u8]),
     /// (StatusCode,Data)
     /// Close may contain data.
-    /// You should call close after receiving this (if you did not already).
+    /// Close reply has already been sent, you do not need to call close.
     Close(Option<u16>, &'a [u8]),
 }
 
@@ -51,16 +421,19 @@ impl State {
 
 /// WebSocket interface.
 ///
-/// WebSocket does not send pings/pongs automatically or close replies.
+/// WebSocket replies to close automatically. Pings/pongs are only sent automatically
+/// if enabled with CallBuilder::ws_auto_pong and CallBuilder::ws_keepalive.
 ///
 /// If received ping, you should send pong back.
 /// You can also just send pong which will not invoke a response.
 /// You should send ping periodically as you never know if your connection
 /// is actually alive without it.
 ///
-/// If WSPacket::Close(_) returned you should call close and then finish.
-/// If you want to initiate close, you should call close and wait for WSPacket::Close(_),
-/// then call finish. That is the standard way of closing ws connections.
+/// If WSPacket::Close(_) returned, close reply has already been sent and connection
+/// is shut down on next perform. You can call finish right away.
+/// If you want to initiate close, call shutdown and keep calling perform or recv_packet
+/// until Error::Closed. Connection is shut down once server replies with close or timeout
+/// passes. Final close code and reason are returned by close_status.
 pub struct WebSocket {
     id: Call,
     state: State,
@@ -75,11 +448,49 @@ pub struct WebSocket {
     curframe: [u8; 16],
     curframe_len: u8,
     curframe_pos: u8,
+    opts: WsOpts,
+    deflate: Option<Box<Deflate>>,
+    // Scratch buffer for compressing outgoing messages.
+    deflate_buf: Vec<u8>,
+    max_msg: usize,
+    protocol: Option<String>,
+    // Reassembled message for recv_message.
+    msg_buf: Vec<u8>,
+    // Start of a character split by the last received text frame.
+    utf8_tail: Vec<u8>,
+    last_recv: Instant,
+    last_ping: Instant,
+    close_sent: bool,
+    // Both sides sent close, shut down once send buffer is empty.
+    close_done: bool,
+    close_deadline: Option<Instant>,
+    close_status: Option<(CloseCode, String)>,
+    // Compression failed in send_text or send_bin.
+    send_err: Option<crate::Error>,
+    // A send was rejected because queue was full.
+    send_blocked: bool,
+    send_drained: bool,
 }
 
 impl WebSocket {
-    pub(crate) fn new(id: Call, send_buf: Vec<u8>) -> WebSocket {
+    pub(crate) fn new(id: Call, send_buf: Vec<u8>, opts: WsOpts, max_msg: usize) -> WebSocket {
         WebSocket {
+            opts,
+            deflate: None,
+            deflate_buf: Vec::new(),
+            max_msg,
+            protocol: None,
+            msg_buf: Vec::new(),
+            utf8_tail: Vec::new(),
+            last_recv: Instant::now(),
+            last_ping: Instant::now(),
+            close_sent: false,
+            close_done: false,
+            close_deadline: None,
+            close_status: None,
+            send_err: None,
+            send_blocked: false,
+            send_drained: false,
             send_buf,
             send_buf_pos: 0,
             send_middle: false,
@@ -100,7 +511,7 @@ impl WebSocket {
     }
 
     pub fn empty() -> WebSocket {
-        let mut r = Self::new(Call::empty(), Vec::new());
+        let mut r = Self::new(Call::empty(), Vec::new(), WsOpts::default(), 0);
         r.state = State::Done;
         r
     }
@@ -114,12 +525,45 @@ impl WebSocket {
         self.state == State::Active
     }
 
+    /// True if permessage-deflate was negotiated with server.
+    pub fn is_deflate(&self) -> bool {
+        self.deflate.is_some()
+    }
+
+    /// Subprotocol selected by server from those offered with CallBuilder::ws_protocols.
+    /// None if server did not select any or setup is not finished yet.
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+
     /// How many bytes are in send buffer waiting to be sent.
     /// Does not take into account any send_bin_inplace packets.
     pub fn sendq_len(&self) -> usize {
         self.send_buf.len() - self.send_buf_pos
     }
 
+    /// When perform must be called next to send a keepalive ping or fail with
+    /// Error::WebSocketTimeout (CallBuilder::ws_keepalive). Httpc::timeout returns
+    /// the WebSocket call once it passes. Use it to limit poll timeout.
+    /// None if keepalive is off or WebSocket is not active.
+    pub fn keepalive_deadline(&self) -> Option<Instant> {
+        let interval = self.opts.ping_interval?;
+        if self.state != State::Active || self.close_sent {
+            return None;
+        }
+        if self.last_ping > self.last_recv {
+            Some(self.last_ping + self.opts.ping_timeout)
+        } else {
+            Some(self.last_recv + interval)
+        }
+    }
+
+    /// Returns true once after send queue has been sent down to low-water mark
+    /// set by CallBuilder::ws_send_queue, following a send that returned Error::WouldBlock.
+    pub fn drained(&mut self) -> bool {
+        ::std::mem::take(&mut self.send_drained)
+    }
+
     pub fn is_ref(&self, r: CallRef) -> bool {
         self.id.is_ref(r)
     }
@@ -155,39 +599,109 @@ impl WebSocket {
 
     /// Ping server. Body if present is capped at 125 bytes.
     pub fn ping(&mut self, body: Option<&[u8]>) {
-        self.send_buf_append(9, None, true, Self::limit_body(125, body))
+        self.send_buf_append(9, None, true, false, Self::limit_body(125, body))
     }
 
     /// A reply to ping or not. Both are valid. Body if present is capped at 125 bytes.
     pub fn pong(&mut self, body: Option<&[u8]>) {
-        self.send_buf_append(10, None, true, Self::limit_body(125, body))
+        self.send_buf_append(10, None, true, false, Self::limit_body(125, body))
     }
 
-    /// A reply to close or initiate close.
-    /// close must be sent by both parties.
-    /// Body if present is capped at 125 bytes.
+    /// Initiate close. Does nothing if close was already sent.
+    /// close must be sent by both parties, reply to server close is sent automatically.
+    /// Body if present is capped at 123 bytes.
     pub fn close(&mut self, status: Option<u16>, body: Option<&[u8]>) {
-        self.send_buf_append(8, status, true, Self::limit_body(123, body))
+        if self.close_sent {
+            return;
+        }
+        self.close_sent = true;
+        self.send_buf_append(8, status, true, false, Self::limit_body(123, body))
+    }
+
+    /// Start close handshake. Sends close and waits for server close until timeout,
+    /// then shuts down connection. Reason is capped at 123 bytes.
+    ///
+    /// Keep calling perform or recv_packet until they return Error::Closed.
+    /// Result is returned by close_status.
+    pub fn shutdown(&mut self, code: CloseCode, reason: &str, timeout: Duration) {
+        let mut end = reason.len().min(123);
+        while !reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.close(Some(code.into()), Some(&reason.as_bytes()[..end]));
+        self.close_deadline = Some(Instant::now() + timeout);
+    }
+
+    /// Close code and reason once connection is closed.
+    /// Code is CloseCode::NoStatus if server close had no code and CloseCode::Abnormal
+    /// if connection was closed without a close handshake.
+    pub fn close_status(&self) -> Option<(CloseCode, &str)> {
+        self.close_status
+            .as_ref()
+            .map(|(code, reason)| (*code, reason.as_str()))
+    }
+
+    // Compress data frame into deflate_buf if permessage-deflate is active.
+    // Returns RSV1 bit if deflate_buf contains the frame payload.
+    fn send_deflate(&mut self, op: u8, fin: bool, pkt: &[u8]) -> crate::Result<Option<bool>> {
+        let deflate = match self.deflate {
+            Some(ref mut d) if op <= 2 && d.compress.is_some() => d,
+            _ => return Ok(None),
+        };
+        // RSV1 is only set on the first frame of a message.
+        let rsv1 = !deflate.send_compressed;
+        deflate.send_compressed = !fin;
+        self.deflate_buf.truncate(0);
+        if let Err(e) = deflate.deflate(fin, pkt, &mut self.deflate_buf) {
+            // Compression context is unusable, connection can not continue.
+            self.state = State::Finish;
+            return Err(e);
+        }
+        Ok(Some(rsv1))
+    }
+
+    // Append data frame, compressed if permessage-deflate is active.
+    fn send_data(&mut self, op: u8, fin: bool, pkt: &[u8]) -> crate::Result<()> {
+        match self.send_deflate(op, fin, pkt)? {
+            Some(rsv1) => {
+                let deflate_buf = ::std::mem::take(&mut self.deflate_buf);
+                self.send_buf_append(op, None, fin, rsv1, Some(&deflate_buf));
+                self.deflate_buf = deflate_buf;
+            }
+            None => self.send_buf_append(op, None, fin, false, Some(pkt)),
+        }
+        Ok(())
     }
 
     // only append do not send.
-    fn send_buf_append(&mut self, op: u8, status: Option<u16>, fin: bool, body: Option<&[u8]>) {
+    fn send_buf_append(
+        &mut self,
+        op: u8,
+        status: Option<u16>,
+        fin: bool,
+        rsv1: bool,
+        body: Option<&[u8]>,
+    ) {
         let body_sz = if let Some(body) = body { body.len() } else { 0 };
+        let status_sz = if status.is_some() { 2 } else { 0 };
         let mut send_buf = ::std::mem::replace(&mut self.send_buf, Vec::new());
         let start_pos = send_buf.len();
-        send_buf.resize(start_pos + body_sz + 16, 0);
-        let pkt = if let Some(body) = body { body } else { &[] };
+        send_buf.resize(start_pos + status_sz + body_sz + 16, 0);
         let mut mask = [0u8; 4];
-        let fsz = self.fill_frame(fin, op, pkt, &mut send_buf[start_pos..], &mut mask[..]);
+        let fsz = self.fill_frame(
+            fin,
+            op,
+            rsv1,
+            status_sz + body_sz,
+            &mut send_buf[start_pos..],
+            &mut mask[..],
+        );
         let mut mask_pos = 0;
-        let status_sz = if let Some(status) = status {
+        if let Some(status) = status {
             BigEndian::write_u16(&mut send_buf[start_pos + fsz..], status);
             Self::mask_inplace(&mask, &mut send_buf[start_pos + fsz..start_pos + fsz + 2]);
             mask_pos = 2;
-            2
-        } else {
-            0
-        };
+        }
         if let Some(body) = body {
             Self::mask_to(
                 &mask,
@@ -196,10 +710,7 @@ impl WebSocket {
                 &mut send_buf[start_pos + fsz + status_sz..],
             );
         }
-        if fsz + status_sz < 16 {
-            let len = send_buf.len();
-            send_buf.truncate(len - (16 - fsz - status_sz));
-        }
+        send_buf.truncate(start_pos + fsz + status_sz + body_sz);
         self.send_buf = send_buf;
         // self.do_send_buf(htp, poll)
     }
@@ -222,12 +733,26 @@ impl WebSocket {
                 //         diff,
                 //     );
                 // }
-                self.send_buf.drain(..self.send_buf_pos);
+                send_buf.drain(..self.send_buf_pos);
                 self.send_buf_pos = 0;
                 // send_buf.truncate(diff);
             }
         }
         self.send_buf = send_buf;
+        if self.send_blocked && self.sendq_len() <= self.opts.send_low_water {
+            self.send_blocked = false;
+            self.send_drained = true;
+        }
+        Ok(())
+    }
+
+    fn check_send_queue(&mut self, len: usize) -> crate::Result<()> {
+        let queued = self.sendq_len();
+        let max = self.opts.max_send_queue;
+        if max > 0 && queued > 0 && queued + len > max {
+            self.send_blocked = true;
+            return Err(crate::Error::WouldBlock);
+        }
         Ok(())
     }
 
@@ -245,6 +770,9 @@ impl WebSocket {
 
     fn stop(&mut self, htp: &mut Httpc) {
         self.state = State::Done;
+        if self.close_status.is_none() {
+            self.close_status = Some((CloseCode::Abnormal, String::new()));
+        }
         let call = ::std::mem::replace(&mut self.id, Call::empty());
         let buf = ::std::mem::replace(&mut self.send_buf, Vec::new());
         if buf.capacity() > 0 {
@@ -254,19 +782,42 @@ impl WebSocket {
     }
 
     /// Send text packet. Data gets copied out into an internal buffer, as it must be
-    /// masked before sending.
+    /// masked before sending. It is compressed if permessage-deflate is active.
     /// No bytes will have been sent after calling this. Actual sending is done by recv_packet or perform.
+    ///
+    /// Send queue limit (CallBuilder::ws_send_queue) is not checked, use try_send_text.
     pub fn send_text(&mut self, fin: bool, pkt: &str) {
-        self.send_buf_append(1, None, fin, Some(pkt.as_bytes()));
-        // self.do_send_buf(htp,poll)
+        self.send_or_fail(1, fin, pkt.as_bytes());
     }
 
     /// Send binary packet. Data gets copied out into an internal buffer, as it must be
-    /// masked before sending.
+    /// masked before sending. It is compressed if permessage-deflate is active.
     /// No bytes will have been sent after calling this. Actual sending is done by recv_packet or perform.
+    ///
+    /// Send queue limit (CallBuilder::ws_send_queue) is not checked, use try_send_bin.
     pub fn send_bin(&mut self, fin: bool, pkt: &[u8]) {
-        self.send_buf_append(2, None, fin, Some(pkt));
-        // self.do_send_buf(htp,poll)
+        self.send_or_fail(2, fin, pkt);
+    }
+
+    /// Like send_text, but returns Error::WouldBlock if send queue limit is reached
+    /// (CallBuilder::ws_send_queue). Packet is not queued in that case.
+    pub fn try_send_text(&mut self, fin: bool, pkt: &str) -> crate::Result<()> {
+        self.check_send_queue(pkt.len())?;
+        self.send_data(1, fin, pkt.as_bytes())
+    }
+
+    /// Like send_bin, but returns Error::WouldBlock if send queue limit is reached
+    /// (CallBuilder::ws_send_queue). Packet is not queued in that case.
+    pub fn try_send_bin(&mut self, fin: bool, pkt: &[u8]) -> crate::Result<()> {
+        self.check_send_queue(pkt.len())?;
+        self.send_data(2, fin, pkt)
+    }
+
+    // Compression error is returned by next perform.
+    fn send_or_fail(&mut self, op: u8, fin: bool, pkt: &[u8]) {
+        if let Err(e) = self.send_data(op, fin, pkt) {
+            self.send_err = Some(e);
+        }
     }
 
     /// Send websocket packet. It will create a frame for entire size of pkt slice.
@@ -277,7 +828,8 @@ impl WebSocket {
     /// If starting from the middle, fin is ignored and will be used to start the next packet.
     ///
     /// inplace send will mask pkt directly and send it. This is the most efficient method but leaves
-    /// pkt scrambled.
+    /// pkt scrambled. It is never compressed, so it must not continue a message started
+    /// with send_text or send_bin while permessage-deflate is active.
     pub fn send_bin_inplace(
         &mut self,
         htp: &mut Httpc,
@@ -309,7 +861,8 @@ impl WebSocket {
             if self.send_lover == 0 && self.curframe_pos == 0 {
                 let mut frame = [0u8; 16];
                 let mut mask = [0u8; 4];
-                self.curframe_len = self.fill_frame(fin, 2, pkt, &mut frame, &mut mask) as u8;
+                self.curframe_len =
+                    self.fill_frame(fin, 2, false, pkt.len(), &mut frame, &mut mask) as u8;
                 let len = self.curframe_len as usize;
                 let sent = self.call_send(htp, poll, &frame[0..len])?;
                 if sent == len {
@@ -356,9 +909,84 @@ impl WebSocket {
         Ok(consumed)
     }
 
+    /// Send binary packet assembled from multiple slices. Slices are masked in place
+    /// (leaving them scrambled like send_bin_inplace) and written to socket with a single
+    /// vectored write if possible. Only the part socket did not accept is copied into the
+    /// internal buffer and sent by recv_packet or perform.
+    ///
+    /// If permessage-deflate is active, slices are left untouched and compressed like send_bin.
+    /// Must not be called while a send_bin_inplace packet is only partially sent.
+    ///
+    /// Returns Error::WouldBlock if send queue limit is reached (CallBuilder::ws_send_queue).
+    pub fn send_bin_vectored(
+        &mut self,
+        htp: &mut Httpc,
+        poll: &Registry,
+        fin: bool,
+        bufs: &mut [&mut [u8]],
+    ) -> crate::Result<()> {
+        if self.state == State::Done {
+            return Err(crate::Error::Closed);
+        }
+        if self.state == State::Finish {
+            self.stop(htp);
+            return Err(crate::Error::Closed);
+        }
+        if self.send_lover > 0 || self.curframe_pos > 0 {
+            return Err(crate::Error::Other("send_bin_inplace packet not finished"));
+        }
+        let len = bufs.iter().map(|b| b.len()).sum();
+        self.check_send_queue(len)?;
+        if self.deflate.as_ref().is_some_and(|d| d.compress.is_some()) {
+            return self.send_data(2, fin, &bufs.concat());
+        }
+
+        let mut frame = [0u8; 16];
+        let mut mask = [0u8; 4];
+        let fsz = self.fill_frame(fin, 2, false, len, &mut frame, &mut mask);
+        let mut off = 0;
+        for b in bufs.iter_mut() {
+            let m = [
+                mask[off & 3],
+                mask[(off + 1) & 3],
+                mask[(off + 2) & 3],
+                mask[(off + 3) & 3],
+            ];
+            Self::mask_inplace(&m, b);
+            off += b.len();
+        }
+
+        if self.state == State::Active && !self.send_buf.is_empty() {
+            self.do_send_buf(htp, poll)?;
+        }
+        let mut sent = 0;
+        if self.state == State::Active && self.send_buf.is_empty() {
+            let mut io = Vec::with_capacity(bufs.len() + 1);
+            io.push(IoSlice::new(&frame[..fsz]));
+            io.extend(bufs.iter().map(|b| IoSlice::new(b)));
+            let st = htp.call_send_vectored(poll, &mut self.id, &io);
+            sent = self.send_result(htp, st)?;
+        }
+        // Queue what was not sent.
+        for b in ::std::iter::once(&frame[..fsz]).chain(bufs.iter().map(|b| &b[..])) {
+            if sent >= b.len() {
+                sent -= b.len();
+            } else {
+                self.send_buf.extend_from_slice(&b[sent..]);
+                sent = 0;
+            }
+        }
+        Ok(())
+    }
+
     fn call_send(&mut self, htp: &mut Httpc, poll: &Registry, pkt: &[u8]) -> crate::Result<usize> {
-        match htp.call_send(poll, &mut self.id, Some(pkt)) {
-            SendState::Wait => Ok(0),
+        let st = htp.call_send(poll, &mut self.id, Some(pkt));
+        self.send_result(htp, st)
+    }
+
+    fn send_result(&mut self, htp: &mut Httpc, st: SendState) -> crate::Result<usize> {
+        match st {
+            SendState::Wait | SendState::WaitContinue => Ok(0),
             SendState::Receiving => {
                 self.stop(htp);
                 Err(crate::Error::Closed)
@@ -402,7 +1030,8 @@ impl WebSocket {
         &mut self,
         fin: bool,
         mut op: u8,
-        pkt: &[u8],
+        rsv1: bool,
+        len: usize,
         frame: &mut [u8],
         mask_bytes: &mut [u8],
     ) -> usize {
@@ -418,19 +1047,22 @@ impl WebSocket {
         } else {
             frame[pos] = op;
         }
+        if rsv1 {
+            frame[pos] |= 0b0100_0000;
+        }
         pos += 1;
-        if pkt.len() <= 125 {
-            frame[pos] = (pkt.len() as u8) | 0b1000_0000;
+        if len <= 125 {
+            frame[pos] = (len as u8) | 0b1000_0000;
             pos += 1;
-        } else if pkt.len() <= u16::max_value() as usize {
+        } else if len <= u16::max_value() as usize {
             frame[pos] = 126 | 0b1000_0000;
             pos += 1;
-            BigEndian::write_u16(&mut frame[pos..pos + 2], pkt.len() as u16);
+            BigEndian::write_u16(&mut frame[pos..pos + 2], len as u16);
             pos += 2;
         } else {
             frame[pos] = 127 | 0b1000_0000;
             pos += 1;
-            BigEndian::write_u64(&mut frame[pos..pos + 8], pkt.len() as u64);
+            BigEndian::write_u64(&mut frame[pos..pos + 8], len as u64);
             pos += 8;
         }
         let mask = ::rand::random::<u32>();
@@ -441,6 +1073,9 @@ impl WebSocket {
     }
 
     /// You should call this in a loop until you get WSPacket::None.
+    ///
+    /// Text fragments are validated as they arrive. If a character is split between
+    /// fragments, it is returned whole with the fragment it ends in.
     pub fn recv_packet<'a>(
         &mut self,
         htp: &'a mut Httpc,
@@ -455,72 +1090,278 @@ impl WebSocket {
             // htp.peek_body(&self.id, &mut self.recv_lover);
             self.perform(htp, poll)?;
         }
-        self.read_packet(htp)
+        self.read_packet(htp, poll)
+    }
+
+    /// Like recv_packet, but fragmented text and binary messages are reassembled and
+    /// returned whole as WSPacket::Text(true, ..) or WSPacket::Binary(true, ..).
+    /// Control packets are returned as they arrive, also in the middle of a fragmented message.
+    /// Fragments may split a character.
+    /// Messages larger than CallBuilder::ws_max_message fail with Error::ResponseTooBig.
+    ///
+    /// Do not mix with recv_packet on the same WebSocket.
+    /// You should call this in a loop until you get WSPacket::None.
+    pub fn recv_message<'a>(
+        &'a mut self,
+        htp: &'a mut Httpc,
+        poll: &Registry,
+    ) -> crate::Result<WSPacket<'a>> {
+        if self.state.is_init() {
+            self.perform(htp, poll)?;
+            return Ok(WSPacket::None);
+        } else if self.state == State::Done {
+            return Err(crate::Error::Closed);
+        } else {
+            self.perform(htp, poll)?;
+        }
+        loop {
+            let frame = match self.next_frame(htp, poll)? {
+                Some(frame) => frame,
+                None => return Ok(WSPacket::None),
+            };
+            if frame.op >= 8 {
+                return self.frame_packet(htp, frame);
+            }
+            if !frame.cont {
+                self.msg_buf.truncate(0);
+            }
+            let data = frame.data(htp, &self.id);
+            if self.msg_buf.len() + data.len() > self.max_msg {
+                let e = crate::Error::ResponseTooBig;
+                return Err(self.fail(htp, poll, CloseCode::TooBig, e));
+            }
+            self.msg_buf.extend_from_slice(data);
+            if !frame.fin {
+                continue;
+            }
+            if frame.op == 2 {
+                return Ok(WSPacket::Binary(true, &self.msg_buf));
+            }
+            // Fragments were validated by next_frame.
+            return ::std::str::from_utf8(&self.msg_buf)
+                .map(|s| WSPacket::Text(true, s))
+                .map_err(|_| crate::Error::WebSocketProtocol("invalid UTF-8 in text message"));
+        }
+    }
+
+    fn read_packet<'a>(
+        &mut self,
+        htp: &'a mut Httpc,
+        poll: &Registry,
+    ) -> crate::Result<WSPacket<'a>> {
+        match self.next_frame(htp, poll)? {
+            Some(frame) => self.frame_packet(htp, frame),
+            None => Ok(WSPacket::None),
+        }
     }
 
-    fn read_packet<'a>(&mut self, htp: &'a mut Httpc) -> crate::Result<WSPacket<'a>> {
+    // Parse next received frame and decompress it if needed.
+    fn next_frame(&mut self, htp: &mut Httpc, poll: &Registry) -> crate::Result<Option<Frame>> {
         // We can only return one packet at a time, but we can receive multiple packets at the same time.
         // So we use recv_lover as a receive buffer offset.
         // peek_body will fix recv_lover and set it to 0 if everything has been read from buffer.
-        let slice = htp.peek_body(&self.id, &mut self.recv_lover);
-        if let Some((fin, op, mut pos, mut len)) = self.parse_packet(slice) {
-            self.recv_lover += pos + len;
-            match op {
-                _ if (self.cur_op == 1 && op == 0) || op == 1 => {
-                    if op != 0 && !fin {
-                        self.cur_op = 1;
-                    }
-                    if let Ok(s) = ::std::str::from_utf8(&slice[pos..pos + len]) {
-                        return Ok(WSPacket::Text(fin, s));
-                    } else {
-                        self.state = State::Finish;
-                        return Err(crate::Error::WebSocketParse);
-                    }
+        let (slice, buf) = htp.peek_body_ws(&self.id, &mut self.recv_lover);
+        let (fin, rsv1, op, pos, mut len) = match self.parse_packet(slice) {
+            Ok(Some(v)) => v,
+            Ok(None) => return Ok(None),
+            Err(e) => {
+                let e = crate::Error::WebSocketProtocol(e);
+                return Err(self.fail(htp, poll, CloseCode::Protocol, e));
+            }
+        };
+        let base = self.recv_lover;
+        self.recv_lover += pos + len;
+        let cont = op == 0;
+        let op = match op {
+            0 if self.cur_op != 0 => self.cur_op,
+            1 | 2 if self.cur_op == 0 => op,
+            0 => {
+                let e = crate::Error::WebSocketProtocol("continuation frame without a message");
+                return Err(self.fail(htp, poll, CloseCode::Protocol, e));
+            }
+            1 | 2 => {
+                let e = crate::Error::WebSocketProtocol("new message before previous finished");
+                return Err(self.fail(htp, poll, CloseCode::Protocol, e));
+            }
+            _ => op,
+        };
+        if rsv1 && (op >= 8 || cont || self.deflate.is_none()) {
+            let e = crate::Error::WebSocketProtocol("unexpected RSV1 bit");
+            return Err(self.fail(htp, poll, CloseCode::Protocol, e));
+        }
+        if op == 8 {
+            let status = parse_close(&slice[pos..pos + len]);
+            self.on_close(htp, poll, status)?;
+            return Ok(Some(Frame {
+                fin,
+                op,
+                cont,
+                base,
+                pos,
+                len,
+                buffered: false,
+            }));
+        }
+        if op <= 2 {
+            self.cur_op = if fin { 0 } else { op };
+        } else if op == 9 && self.opts.auto_pong {
+            self.pong(Some(&slice[pos..pos + len]));
+        }
+        // Character split by previous text frame goes in front of this one.
+        let mut buffered = op == 1 && !self.utf8_tail.is_empty();
+        if buffered {
+            buf.truncate(0);
+            buf.extend_from_slice(&self.utf8_tail);
+            self.utf8_tail.truncate(0);
+        }
+        let mut inflated = false;
+        if let Some(ref mut deflate) = self.deflate {
+            if op <= 2 && (rsv1 || (cont && deflate.recv_compressed)) {
+                deflate.recv_compressed = !fin;
+                if !buffered {
+                    buf.truncate(0);
                 }
-                _ if (self.cur_op == 2 && op == 0) || op == 2 => {
-                    if op != 0 && !fin {
-                        self.cur_op = 2;
-                    }
-                    return Ok(WSPacket::Binary::<'a>(fin, &slice[pos..pos + len]));
+                if let Err(e) = deflate.inflate(fin, &slice[pos..pos + len], self.max_msg, buf) {
+                    let code = match e {
+                        crate::Error::ResponseTooBig => CloseCode::TooBig,
+                        _ => CloseCode::Protocol,
+                    };
+                    return Err(self.fail(htp, poll, code, e));
                 }
-                8 => {
-                    if !self.closing {
-                        self.closing = true;
-                    }
-                    if len >= 2 {
-                        let v = BigEndian::read_u16(&slice[pos..pos + 2]);
-                        pos += 2;
-                        len -= 2;
-                        return Ok(WSPacket::Close::<'a>(Some(v), &slice[pos..pos + len]));
-                    } else {
-                        return Ok(WSPacket::Close::<'a>(None, &[]));
-                    }
+                inflated = true;
+            }
+        }
+        if buffered && !inflated {
+            buf.extend_from_slice(&slice[pos..pos + len]);
+        }
+        buffered |= inflated;
+        if op == 1 {
+            // Validate text as it arrives. Incomplete character at the end of a fragment
+            // is returned with the next one.
+            let data = if buffered {
+                &buf[..]
+            } else {
+                &slice[pos..pos + len]
+            };
+            let valid = match ::std::str::from_utf8(data) {
+                Ok(_) => data.len(),
+                Err(e) if e.error_len().is_none() && !fin => e.valid_up_to(),
+                Err(_) => {
+                    let e = crate::Error::WebSocketProtocol("invalid UTF-8 in text frame");
+                    return Err(self.fail(htp, poll, CloseCode::InvalidData, e));
                 }
-                9 => {
-                    return Ok(WSPacket::Ping::<'a>(&slice[pos..pos + len]));
+            };
+            self.utf8_tail.extend_from_slice(&data[valid..]);
+            if buffered {
+                buf.truncate(valid);
+            } else {
+                len = valid;
+            }
+        }
+        Ok(Some(Frame {
+            fin,
+            op,
+            cont,
+            base,
+            pos,
+            len,
+            buffered,
+        }))
+    }
+
+    // Protocol violation. Send close with code and fail connection.
+    fn fail(
+        &mut self,
+        htp: &mut Httpc,
+        poll: &Registry,
+        code: CloseCode,
+        err: crate::Error,
+    ) -> crate::Error {
+        self.close(Some(code.into()), None);
+        if self.close_status.is_none() {
+            self.close_status = Some((code, String::new()));
+        }
+        let _ = self.do_send_buf(htp, poll);
+        self.state = State::Finish;
+        err
+    }
+
+    // Received close. Reply if server initiated it and shut down once reply is sent.
+    fn on_close(
+        &mut self,
+        htp: &mut Httpc,
+        poll: &Registry,
+        status: Result<(CloseCode, String), (CloseCode, &'static str)>,
+    ) -> crate::Result<()> {
+        self.closing = true;
+        self.close_done = true;
+        match status {
+            Ok((code, reason)) => {
+                if code == CloseCode::NoStatus {
+                    self.close(None, None);
+                } else {
+                    self.close(Some(code.into()), None);
                 }
-                10 => {
-                    return Ok(WSPacket::Pong::<'a>(&slice[pos..pos + len]));
+                if self.close_status.is_none() {
+                    self.close_status = Some((code, reason));
                 }
-                _ => {
-                    self.state = State::Finish;
-                    return Err(crate::Error::WebSocketParse);
+                self.do_send_buf(htp, poll)
+            }
+            Err((code, e)) => {
+                let e = crate::Error::WebSocketProtocol(e);
+                Err(self.fail(htp, poll, code, e))
+            }
+        }
+    }
+
+    fn frame_packet<'a>(&self, htp: &'a mut Httpc, frame: Frame) -> crate::Result<WSPacket<'a>> {
+        let data = frame.data(htp, &self.id);
+        match frame.op {
+            // Validated by next_frame.
+            1 => ::std::str::from_utf8(data)
+                .map(|s| WSPacket::Text(frame.fin, s))
+                .map_err(|_| crate::Error::WebSocketProtocol("invalid UTF-8 in text frame")),
+            2 => Ok(WSPacket::Binary(frame.fin, data)),
+            8 => {
+                if data.len() >= 2 {
+                    let v = BigEndian::read_u16(&data[..2]);
+                    Ok(WSPacket::Close(Some(v), &data[2..]))
+                } else {
+                    Ok(WSPacket::Close(None, &[]))
                 }
             }
+            9 => Ok(WSPacket::Ping(data)),
+            _ => Ok(WSPacket::Pong(data)),
         }
-        Ok(WSPacket::None)
     }
 
-    fn parse_packet(&self, pkt: &[u8]) -> Option<(bool, u8, usize, usize)> {
-        // let mut rdr = Reader::new(Input::from(pkt));
-        let mut pos = 0;
-        let b: u8 = *pkt.get(pos)?;
-        pos += 1;
-        let fin = ((b & 0b1000_0000) >> 7) == 1;
-        let op = b & 0b0000_1111;
-        let b: u8 = *pkt.get(pos)?;
-        pos += 1;
-        let mut len: u64 = (b & 0b0111_1111) as u64;
+    // Returns None if frame has not been fully received yet.
+    fn parse_packet(&self, pkt: &[u8]) -> Result<Option<FrameHdr>, &'static str> {
+        if pkt.len() < 2 {
+            return Ok(None);
+        }
+        let fin = (pkt[0] & 0b1000_0000) != 0;
+        let rsv1 = (pkt[0] & 0b0100_0000) != 0;
+        if pkt[0] & 0b0011_0000 != 0 {
+            return Err("RSV2 or RSV3 bit set");
+        }
+        let op = pkt[0] & 0b0000_1111;
+        if (3..=7).contains(&op) || op > 10 {
+            return Err("reserved opcode");
+        }
+        if pkt[1] & 0b1000_0000 != 0 {
+            return Err("masked frame from server");
+        }
+        let mut len: u64 = (pkt[1] & 0b0111_1111) as u64;
+        if op >= 8 {
+            if !fin {
+                return Err("fragmented control frame");
+            }
+            if len > 125 {
+                return Err("control frame over 125 bytes");
+            }
+        }
+        let mut pos = 2;
         let nb = if len == 126 {
             len = 0;
             2
@@ -530,19 +1371,21 @@ impl WebSocket {
         } else {
             0
         };
-        for _ in 0..nb {
-            len <<= 8;
-            len |= (*pkt.get(pos)?) as u64;
-            pos += 1;
+        if pkt.len() < pos + nb {
+            return Ok(None);
+        }
+        for b in pkt[pos..pos + nb].iter() {
+            len = (len << 8) | (*b as u64);
         }
+        pos += nb;
         if len > u32::max_value() as u64 {
-            return None;
+            return Err("frame over 4GB");
         }
         let len = len as usize;
-        if (len as usize) + pos <= pkt.len() {
-            return Some((fin, op, pos, len));
+        if len + pos <= pkt.len() {
+            return Ok(Some((fin, rsv1, op, pos, len)));
         }
-        None
+        Ok(None)
     }
 
     fn switch(
@@ -551,21 +1394,54 @@ impl WebSocket {
         poll: &Registry,
         resp: crate::Response,
     ) -> crate::Result<()> {
-        if resp.status != 101 {
-            self.stop(htp);
-            return Err(crate::Error::WebSocketFail(resp));
+        match check_handshake(&self.opts, &resp) {
+            Ok(protocol) => {
+                self.protocol = protocol;
+            }
+            Err(reason) => {
+                self.stop(htp);
+                return Err(crate::Error::WebSocketFail(reason, resp));
+            }
         }
-        if !resp.ws {
-            self.stop(htp);
-            return Err(crate::Error::WebSocketFail(resp));
+        match Deflate::negotiate(self.opts.deflate.as_ref(), &resp) {
+            Some(deflate) => {
+                self.deflate = deflate.map(Box::new);
+            }
+            None => {
+                self.stop(htp);
+                return Err(crate::Error::WebSocketFail(WsFail::Extension, resp));
+            }
         }
         self.state = State::Active;
+        self.last_recv = Instant::now();
+        self.last_ping = self.last_recv;
+        htp.set_ws_deadline(&self.id, self.keepalive_deadline());
         if self.send_buf.len() > 0 {
             return self.do_send_buf(htp, poll);
         }
         Ok(())
     }
 
+    // Send ping if idle for ping interval, fail if nothing was received within timeout after ping.
+    fn keepalive(&mut self, htp: &mut Httpc, poll: &Registry) -> crate::Result<()> {
+        let interval = match self.opts.ping_interval {
+            Some(interval) => interval,
+            None => return Ok(()),
+        };
+        let now = Instant::now();
+        if self.last_ping > self.last_recv {
+            if now.saturating_duration_since(self.last_ping) >= self.opts.ping_timeout {
+                self.stop(htp);
+                return Err(crate::Error::WebSocketTimeout);
+            }
+        } else if now.saturating_duration_since(self.last_recv) >= interval {
+            self.last_ping = now;
+            self.ping(None);
+            self.do_send_buf(htp, poll)?;
+        }
+        Ok(())
+    }
+
     /// Perform socket operation.
     pub fn perform(&mut self, htp: &mut Httpc, poll: &Registry) -> crate::Result<()> {
         if self.state == State::Active {
@@ -573,17 +1449,27 @@ impl WebSocket {
                 self.do_send_buf(htp, poll)?;
             }
             htp.try_truncate(&self.id, &mut self.recv_lover);
+            if self.close_done && self.send_buf.is_empty() {
+                self.stop(htp);
+                return Ok(());
+            }
+            if let Some(deadline) = self.close_deadline {
+                if Instant::now() >= deadline {
+                    self.stop(htp);
+                    return Ok(());
+                }
+            }
         }
         if self.state == State::Done {
             return Err(crate::Error::Closed);
         }
         if self.state == State::Finish {
             self.stop(htp);
-            return Err(crate::Error::Closed);
+            return Err(self.send_err.take().unwrap_or(crate::Error::Closed));
         }
         if self.state == State::InitSending {
             match htp.call_send(poll, &mut self.id, None) {
-                SendState::Wait => {}
+                SendState::Wait | SendState::WaitContinue => {}
                 SendState::Receiving => {
                     self.state = State::InitReceiving;
                 }
@@ -633,7 +1519,11 @@ impl WebSocket {
                         self.state = State::InitSending;
                         break;
                     }
-                    RecvState::ReceivedBody(_) => {}
+                    RecvState::ReceivedBody(sz) => {
+                        if sz > 0 {
+                            self.last_recv = Instant::now();
+                        }
+                    }
                 }
                 // Since ws gets called in a loop rely on that for receiving
                 // Otherwise too much data can be received at the same time and
@@ -643,6 +1533,90 @@ impl WebSocket {
                 }
             }
         }
+        if self.state == State::Active && !self.close_sent {
+            self.keepalive(htp, poll)?;
+        }
+        htp.set_ws_deadline(&self.id, self.keepalive_deadline());
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resp(hdrs: &str) -> crate::Response {
+        let mut r = crate::Response::new();
+        r.hdrs = format!("HTTP/1.1 101 Switching Protocols\r\n{}\r\n", hdrs).into_bytes();
+        r.status = 101;
+        r.ws = true;
+        r
+    }
+
+    #[test]
+    fn ws_close_payload() {
+        assert_eq!(parse_close(&[]), Ok((CloseCode::NoStatus, String::new())));
+        assert_eq!(
+            parse_close(b"\x03\xe8bye"),
+            Ok((CloseCode::Normal, "bye".to_string()))
+        );
+        assert_eq!(
+            parse_close(b"\x0f\xa0"),
+            Ok((CloseCode::Other(4000), String::new()))
+        );
+        let code = |v: &[u8]| parse_close(v).map_err(|e| e.0);
+        assert_eq!(code(&[3]), Err(CloseCode::Protocol));
+        // 999, 1005 and 1006 can not be sent
+        assert_eq!(code(&[3, 0xe7]), Err(CloseCode::Protocol));
+        assert_eq!(code(&[3, 0xed]), Err(CloseCode::Protocol));
+        assert_eq!(code(&[3, 0xee]), Err(CloseCode::Protocol));
+        assert_eq!(code(b"\x03\xe8\xff"), Err(CloseCode::InvalidData));
+    }
+
+    #[test]
+    fn ws_handshake_accept() {
+        // Example from RFC 6455 1.3
+        let mut opts = WsOpts {
+            key: "dGhlIHNhbXBsZSBub25jZQ==".to_string(),
+            ..Default::default()
+        };
+        let ok = "Upgrade: websocket\r\nConnection: Upgrade\r\n\
+                  Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n";
+        assert_eq!(check_handshake(&opts, &resp(ok)), Ok(None));
+        let bad = "Upgrade: websocket\r\nConnection: Upgrade\r\n\
+                   Sec-WebSocket-Accept: AAAALMBiTxaQ9kEYc3RhCg==\r\n";
+        assert_eq!(check_handshake(&opts, &resp(bad)), Err(WsFail::Accept));
+        let no_con = "Upgrade: websocket\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n";
+        assert_eq!(
+            check_handshake(&opts, &resp(no_con)),
+            Err(WsFail::Connection)
+        );
+        let proto = "Upgrade: websocket\r\nConnection: keep-alive, Upgrade\r\n\
+                     Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\nSec-WebSocket-Protocol: chat\r\n";
+        assert_eq!(check_handshake(&opts, &resp(proto)), Err(WsFail::Protocol));
+        opts.protocols = vec!["graphql-transport-ws".to_string(), "chat".to_string()];
+        assert_eq!(
+            check_handshake(&opts, &resp(proto)),
+            Ok(Some("chat".to_string()))
+        );
+        let two = format!("{}Sec-WebSocket-Protocol: chat\r\n", proto);
+        assert_eq!(check_handshake(&opts, &resp(&two)), Err(WsFail::Protocol));
+        let list = proto.replace("chat", "chat, graphql-transport-ws");
+        assert_eq!(check_handshake(&opts, &resp(&list)), Err(WsFail::Protocol));
+    }
+
+    #[test]
+    fn ws_deflate_roundtrip() {
+        let mut tx = Deflate::new(&WsDeflate::default(), false, false);
+        let mut rx = Deflate::new(&WsDeflate::default(), false, false);
+        let big: String = (0..5000).map(|i| format!("{{\"px\":{}}},", i)).collect();
+        for msg in ["hello", "hello", big.as_str()].iter() {
+            let mut out = Vec::new();
+            tx.deflate(true, msg.as_bytes(), &mut out).unwrap();
+            assert!(!out.ends_with(&DEFLATE_TRAILER));
+            let mut msg_out = Vec::new();
+            rx.inflate(true, &out, 1024 * 1024, &mut msg_out).unwrap();
+            assert_eq!(&msg_out[..], msg.as_bytes());
+        }
+    }
+}
@@ -1,14 +1,13 @@
 use crate::connection::Con;
 use crate::tls_api::TlsConnector;
 use crate::types::*;
-use byteorder::{ByteOrder, LittleEndian};
 use data_encoding::{BASE64, HEXLOWER};
 use flate2::read::{DeflateDecoder, GzDecoder};
 use httparse::{self, Response as ParseResp};
 use md5;
 use mio::Interest;
 use std::io::ErrorKind as IoErrorKind;
-use std::io::{Read, Write};
+use std::io::{self, IoSlice, Read, Write};
 use std::str::from_utf8;
 use std::str::FromStr;
 use std::time::{Duration, Instant};
@@ -17,6 +16,8 @@ use std::time::{Duration, Instant};
 enum Dir {
     SendingHdr(usize),
     SendingBody(usize),
+    // Headers sent with Expect: 100-continue, send body at deadline if no reply.
+    WaitContinue(Instant),
     // (bytes_rec, duplex)
     Receiving(usize, bool),
     Done,
@@ -39,17 +40,36 @@ pub(crate) struct CallImpl {
     chunked: ChunkIndex,
     send_encoding: TransferEncoding,
     recv_cont_encoding: Option<ComprAlgo>,
+    // Streamed body (CallBuilderImpl::body_parts) chunk being sent.
+    body_reader: BodyReader,
+    body_encoder: Option<BodyEncoder>,
+    part_buf: Vec<u8>,
+    part_off: usize,
+    // Final response received while waiting for 100 Continue.
+    pending_recv: Option<RecvStateInt>,
+    // Set by WebSocket from WebSocket::keepalive_deadline.
+    ws_deadline: Option<Instant>,
 }
 
+// Size of chunks read from body parts.
+const PART_CHUNK: usize = 32 * 1024;
+
 impl CallImpl {
     pub fn new(
         call_id: u64,
-        b: CallBuilderImpl,
+        mut b: CallBuilderImpl,
         mut buf_hdr: Vec<u8>,
         mut buf_body: Vec<u8>,
     ) -> CallImpl {
         buf_hdr.truncate(0);
         buf_body.truncate(0);
+        if let Some(enc) = b.compress {
+            if !b.body_compressed && !b.body.is_empty() {
+                // Writing to a Vec can not fail.
+                b.body = BodyEncoder::encode(enc, &b.body).unwrap();
+                b.body_compressed = true;
+            }
+        }
         CallImpl {
             call_id,
             dir: Dir::SendingHdr(0),
@@ -62,6 +82,12 @@ impl CallImpl {
             chunked: ChunkIndex::new(),
             send_encoding: TransferEncoding::Identity,
             recv_cont_encoding: None,
+            body_reader: BodyReader::default(),
+            body_encoder: None,
+            part_buf: Vec::new(),
+            part_off: 0,
+            pending_recv: None,
+            ws_deadline: None,
         }
     }
 
@@ -74,6 +100,15 @@ impl CallImpl {
         }
     }
 
+    pub fn set_ws_deadline(&mut self, d: Option<Instant>) {
+        self.ws_deadline = d;
+    }
+
+    // WebSocket must send a ping or check for pong timeout.
+    pub fn ws_keepalive_due(&self, now: Instant) -> bool {
+        matches!(self.ws_deadline, Some(d) if now >= d)
+    }
+
     pub fn call_id(&self) -> u64 {
         self.call_id
     }
@@ -175,31 +210,72 @@ impl CallImpl {
     fn fill_send_req(&mut self, buf: &mut Vec<u8>) {
         buf.extend(self.b.method.as_str().as_bytes());
         buf.extend(b" ");
-        // Self::extend_full_path(buf, self.b.req.uri());
-        buf.extend(&self.b.bytes.path);
-        buf.extend(&self.b.bytes.query);
+        if self.b.method == Method::CONNECT {
+            // authority-form
+            buf.extend(&self.b.bytes.host);
+            buf.extend(b":");
+            buf.extend(itoa::Buffer::new().format(self.b.port).as_bytes());
+        } else {
+            // Self::extend_full_path(buf, self.b.req.uri());
+            buf.extend(&self.b.bytes.path);
+            buf.extend(&self.b.bytes.query);
+        }
         buf.extend(b" HTTP/1.1\r\n");
         buf.extend(&self.b.bytes.headers);
         // let cl = self.b.req.headers().get(CONTENT_LENGTH);
-        let cl = self.b.content_len_set;
+        // Caller Content-Length is of uncompressed body, length is derived from encoded body.
+        let compressed =
+            self.b.compress.is_some() && (!self.b.body.is_empty() || !self.b.body_parts.is_empty());
+        let cl = self.b.content_len_set && !compressed;
         if cl == false && self.b.body.len() > 0 {
             self.body_sz = self.b.body.len();
+        } else if !cl && !self.b.body_parts.is_empty() {
+            let len = if let Some(enc) = self.b.compress {
+                self.body_encoder = Some(BodyEncoder::new(enc));
+                None
+            } else {
+                self.b
+                    .body_parts
+                    .iter()
+                    .map(|p| p.len())
+                    .sum::<Option<u64>>()
+            };
+            match len {
+                Some(sz) => self.body_sz = sz as usize,
+                None => {
+                    // Length is unknown, body_sz is set once last chunk is read.
+                    self.send_encoding = TransferEncoding::Chunked;
+                    self.body_sz = usize::MAX;
+                    buf.extend(b"Transfer-Encoding: chunked\r\n");
+                }
+            }
         // digest auth requires www-authenticate response first
         // and one must not send send data for that
         } else if cl && !(self.b.digest && self.b.auth.hdr.len() == 0) {
             self.body_sz = self.b.content_len;
         }
-        if self.body_sz > 0 {
+        // Body streamed with call_send, framed by caller.
+        if self.b.transfer_encoding == TransferEncoding::Chunked {
+            self.send_encoding = TransferEncoding::Chunked;
+            self.body_sz = usize::max_value();
+        }
+        if self.body_sz > 0 && self.send_encoding == TransferEncoding::Identity {
             // let mut ar = [0u8; 15];
             let mut sz = itoa::Buffer::new();
-            let szs = sz.format(self.b.body.len());
+            let szs = sz.format(self.body_sz);
             buf.extend(b"Content-Length: ");
             buf.extend(szs.as_bytes());
             buf.extend(b"\r\n");
         }
-        if self.b.transfer_encoding == TransferEncoding::Chunked {
-            self.send_encoding = TransferEncoding::Chunked;
-            self.body_sz = usize::max_value();
+        if self.b.expect_continue && self.body_sz > 0 {
+            buf.extend(b"Expect: 100-continue\r\n");
+        }
+        if let Some(enc) = self.b.compress {
+            if !self.b.body.is_empty() || !self.b.body_parts.is_empty() {
+                buf.extend(b"Content-Encoding: ");
+                buf.extend(enc.as_str().as_bytes());
+                buf.extend(b"\r\n");
+            }
         }
         if self.b.ua_set == false {
             // buf.extend(USER_AGENT.as_str().as_bytes());
@@ -210,22 +286,35 @@ impl CallImpl {
             buf.extend((env!("CARGO_PKG_VERSION")).as_bytes());
             buf.extend(b"\r\n");
         }
-        if self.b.gzip && !self.b.ws {
+        if self.b.gzip && !self.b.ws && !self.b.ae_set {
             buf.extend(b"Accept-Encoding: gzip, deflate\r\n");
         }
         if self.b.ws {
             buf.extend(b"Connection: upgrade\r\n");
             buf.extend(b"Upgrade: websocket\r\n");
             buf.extend(b"Sec-Websocket-Key: ");
-            let mut ar = [0u8; 16];
-            let mut out = [0u8; 32];
-            LittleEndian::write_u64(&mut ar, ::rand::random::<u64>());
-            LittleEndian::write_u64(&mut ar[8..], ::rand::random::<u64>());
-            let enc_len = BASE64.encode_len(ar.len());
-            BASE64.encode_mut(&ar, &mut out[..enc_len]);
-            buf.extend(&out[..enc_len]);
+            buf.extend(self.b.ws_opts.key.as_bytes());
             buf.extend(b"\r\n");
             buf.extend(b"Sec-Websocket-Version: 13\r\n");
+            if !self.b.ws_opts.protocols.is_empty() {
+                buf.extend(b"Sec-WebSocket-Protocol: ");
+                buf.extend(self.b.ws_opts.protocols.join(", ").as_bytes());
+                buf.extend(b"\r\n");
+            }
+            if let Some(ref deflate) = self.b.ws_opts.deflate {
+                buf.extend(b"Sec-WebSocket-Extensions: permessage-deflate");
+                if deflate.client_no_context_takeover {
+                    buf.extend(b"; client_no_context_takeover");
+                }
+                if deflate.server_no_context_takeover {
+                    buf.extend(b"; server_no_context_takeover");
+                }
+                if let Some(bits) = deflate.server_max_window_bits {
+                    buf.extend(b"; server_max_window_bits=");
+                    buf.extend(itoa::Buffer::new().format(bits).as_bytes());
+                }
+                buf.extend(b"\r\n");
+            }
         } else if self.b.con_set == false {
             buf.extend(b"Connection: keep-alive\r\n");
         }
@@ -370,7 +459,7 @@ impl CallImpl {
 
                 let ret = self.event_send_do::<C>(con, cp, 0, &buf[pos..hdr_sz]);
                 self.buf_hdr = buf;
-                if let Dir::SendingBody(_) = self.dir {
+                if let Dir::SendingBody(_) | Dir::WaitContinue(_) = self.dir {
                     self.buf_hdr.truncate(0);
                     // go again
                     return self.event_send::<C>(con, cp, b);
@@ -379,9 +468,21 @@ impl CallImpl {
                 }
                 ret
             }
+            Dir::WaitContinue(deadline) => {
+                if self.wait_continue::<C>(con, cp, deadline)? {
+                    return self.event_send::<C>(con, cp, b);
+                }
+                match self.dir {
+                    Dir::WaitContinue(_) => Ok(SendStateInt::WaitContinue),
+                    _ => Ok(SendStateInt::Receiving),
+                }
+            }
             Dir::SendingBody(pos) if self.b.body.len() > 0 => {
                 self.event_send_do::<C>(con, cp, pos, &[])
             }
+            Dir::SendingBody(pos) if !self.b.body_parts.is_empty() => {
+                self.event_send_parts::<C>(con, cp, pos)
+            }
             Dir::SendingBody(_pos) if b.is_some() => {
                 let b = b.unwrap();
                 self.event_send_do::<C>(con, cp, 0, &b[..])
@@ -390,6 +491,137 @@ impl CallImpl {
         }
     }
 
+    // Write directly to socket of an upgraded (WebSocket) connection.
+    pub(crate) fn event_send_vectored<C: TlsConnector>(
+        &mut self,
+        con: &mut Con,
+        cp: &mut CallParam,
+        bufs: &[IoSlice],
+    ) -> crate::Result<SendStateInt> {
+        match self.dir {
+            Dir::Done => return Ok(SendStateInt::Done),
+            Dir::Receiving(_, true) => {}
+            Dir::Receiving(_, false) => return Ok(SendStateInt::Receiving),
+            _ => return Ok(SendStateInt::Wait),
+        }
+        if !con.is_signalled_wr() {
+            return Ok(SendStateInt::Wait);
+        }
+        if let Err(e) = con.signalled::<C, Vec<u8>>(cp) {
+            con.set_to_close(true);
+            return Err(e);
+        }
+        match Self::con_write(con, cp, bufs)? {
+            Some(sz) => Ok(SendStateInt::SentBody(sz)),
+            None => Ok(SendStateInt::Wait),
+        }
+    }
+
+    // Returns None if socket is not writable.
+    fn con_write(
+        con: &mut Con,
+        cp: &mut CallParam,
+        bufs: &[IoSlice],
+    ) -> crate::Result<Option<usize>> {
+        loop {
+            match con.write_vectored(bufs) {
+                Err(ref ie) if ie.kind() == IoErrorKind::Interrupted => continue,
+                Err(ref ie) if ie.kind() == IoErrorKind::NotConnected => return Ok(None),
+                Err(ref ie) if ie.kind() == IoErrorKind::WouldBlock => {
+                    con.reg(cp.poll, Interest::WRITABLE)?;
+                    return Ok(None);
+                }
+                Ok(sz) if sz > 0 => return Ok(Some(sz)),
+                _ => return Err(crate::Error::Closed),
+            }
+        }
+    }
+
+    // Send body from CallBuilderImpl::body_parts, reading it in chunks.
+    fn event_send_parts<C: TlsConnector>(
+        &mut self,
+        con: &mut Con,
+        cp: &mut CallParam,
+        mut pos: usize,
+    ) -> crate::Result<SendStateInt> {
+        if !con.is_signalled_wr() {
+            return Ok(SendStateInt::Wait);
+        }
+        if let Err(e) = con.signalled::<C, Vec<u8>>(cp) {
+            con.set_to_close(true);
+            return Err(e);
+        }
+        let mut sent = 0;
+        loop {
+            if self.part_off == self.part_buf.len() {
+                self.part_buf.clear();
+                self.part_off = 0;
+                let n = self.read_parts()?;
+                if self.send_encoding == TransferEncoding::Chunked {
+                    if n == 0 {
+                        self.part_buf.extend_from_slice(b"0\r\n\r\n");
+                        self.body_sz = pos + self.part_buf.len();
+                    } else {
+                        let size = format!("{:x}\r\n", n);
+                        self.part_buf.splice(0..0, size.bytes());
+                        self.part_buf.extend_from_slice(b"\r\n");
+                    }
+                } else if n == 0 {
+                    return Err(crate::Error::Other("Body shorter than Content-Length"));
+                }
+            }
+            let buf = &self.part_buf[self.part_off..];
+            let sz = match Self::con_write(con, cp, &[IoSlice::new(buf)])? {
+                Some(sz) => sz,
+                None if sent > 0 => return Ok(SendStateInt::SentBody(sent)),
+                None => return Ok(SendStateInt::Wait),
+            };
+            self.part_off += sz;
+            pos += sz;
+            sent += sz;
+            if pos >= self.body_sz {
+                self.hdr_sz = 0;
+                self.body_sz = 0;
+                self.part_buf = Vec::new();
+                self.dir = Dir::Receiving(0, false);
+                return Ok(SendStateInt::Receiving);
+            }
+            self.dir = Dir::SendingBody(pos);
+        }
+    }
+
+    // Read next chunk of body_parts to part_buf, compressed if compress_body is set.
+    // Returns 0 once body is complete.
+    fn read_parts(&mut self) -> io::Result<usize> {
+        let enc = match self.body_encoder {
+            Some(ref mut enc) => enc,
+            None => {
+                return self
+                    .body_reader
+                    .read(&self.b.body_parts, &mut self.part_buf, PART_CHUNK)
+            }
+        };
+        let mut data = Vec::with_capacity(PART_CHUNK);
+        loop {
+            data.clear();
+            if self
+                .body_reader
+                .read(&self.b.body_parts, &mut data, PART_CHUNK)?
+                == 0
+            {
+                if let Some(enc) = self.body_encoder.take() {
+                    self.part_buf.append(&mut enc.finish()?);
+                }
+                return Ok(self.part_buf.len());
+            }
+            enc.write(&data)?;
+            enc.take(&mut self.part_buf);
+            if !self.part_buf.is_empty() {
+                return Ok(self.part_buf.len());
+            }
+        }
+    }
+
     fn maybe_gunzip(&self, inbuf: Vec<u8>, extbuf: Option<&mut Vec<u8>>) -> crate::Result<Vec<u8>> {
         match self.recv_cont_encoding {
             Some(ComprAlgo::Gzip) => {
@@ -428,11 +660,15 @@ impl CallImpl {
         cp: &mut CallParam,
         b: Option<&mut Vec<u8>>,
     ) -> crate::Result<RecvStateInt> {
+        if let Some(r) = self.pending_recv.take() {
+            return Ok(r);
+        }
         match self.dir {
             Dir::Done if self.buf_body.len() == 0 => {
                 return Ok(RecvStateInt::Done);
             }
             Dir::SendingBody(_) => Ok(RecvStateInt::Sending),
+            Dir::WaitContinue(_) => Ok(RecvStateInt::Sending),
             Dir::SendingHdr(_) => Ok(RecvStateInt::Sending),
             _ => {
                 let rec_pos = if let Dir::Receiving(rec_pos, _) = self.dir {
@@ -570,7 +806,10 @@ impl CallImpl {
                 &Ok(sz) if sz > 0 => {
                     if let Dir::SendingHdr(pos) = self.dir {
                         if self.hdr_sz == pos + sz {
-                            if self.body_sz > 0 {
+                            if self.body_sz > 0 && self.b.expect_continue {
+                                self.dir = Dir::WaitContinue(Instant::now() + self.b.continue_dur);
+                                return Ok(SendStateInt::Wait);
+                            } else if self.body_sz > 0 {
                                 self.dir = Dir::SendingBody(0);
                                 return Ok(SendStateInt::Wait);
                             } else {
@@ -591,10 +830,15 @@ impl CallImpl {
                             return Ok(SendStateInt::Receiving);
                         }
                         self.dir = Dir::SendingBody(pos + sz);
+                        // All of buffer from call_send was written.
+                        if !b.is_empty() && in_pos + sz == b.len() {
+                            return Ok(SendStateInt::SentBody(sz));
+                        }
 
-                        match self.event_send_do::<C>(con, cp, pos + sz, b) {
+                        // in_pos is offset in b, or in body when b is empty.
+                        match self.event_send_do::<C>(con, cp, in_pos + sz, b) {
                             Ok(SendStateInt::SentBody(out)) => {
-                                return Ok(SendStateInt::SentBody(out));
+                                return Ok(SendStateInt::SentBody(sz + out));
                             }
                             Ok(SendStateInt::Wait) => {
                                 return Ok(SendStateInt::SentBody(sz));
@@ -679,47 +923,7 @@ impl CallImpl {
             }
             Ok(bytes_rec) => {
                 if self.hdr_sz == 0 {
-                    let mut auth_info = None;
-                    let mut resp = crate::Response::new();
-                    match self.read_hdr(con, buf, &mut resp, &mut auth_info) {
-                        Ok(()) => {
-                            if self.hdr_sz == 0 {
-                                return Ok(RecvStateInt::Wait);
-                            }
-                            buf.truncate(self.hdr_sz);
-                            ::std::mem::swap(&mut resp.hdrs, buf);
-
-                            if resp.status == 401 {
-                                if let Some(auth) = auth_info {
-                                    return Ok(RecvStateInt::DigestAuth(resp, auth));
-                                } else if self.b.digest {
-                                    return Ok(RecvStateInt::BasicAuth);
-                                }
-                            }
-                            if resp.status == 101 {
-                                return Ok(RecvStateInt::Response(
-                                    resp,
-                                    crate::ResponseBody::Streamed,
-                                ));
-                            } else if resp.status >= 300 && resp.status < 400 {
-                                return Ok(RecvStateInt::Redirect(resp));
-                            }
-                            if self.b.need_chunk_parse {
-                                return Ok(RecvStateInt::Response(
-                                    resp,
-                                    crate::ResponseBody::Streamed,
-                                ));
-                            } else {
-                                return Ok(RecvStateInt::Response(
-                                    resp,
-                                    crate::ResponseBody::Sized(self.body_sz),
-                                ));
-                            }
-                        }
-                        Err(e) => {
-                            return Err(e);
-                        }
-                    }
+                    return self.read_response(con, buf);
                 } else {
                     let (pos, duplex) = if let Dir::Receiving(pos, duplex) = self.dir {
                         (pos, duplex)
@@ -760,6 +964,121 @@ impl CallImpl {
         }
     }
 
+    // Parse response header from buf. On success buf is moved to Response.
+    fn read_response(&mut self, con: &mut Con, buf: &mut Vec<u8>) -> crate::Result<RecvStateInt> {
+        let mut auth_info = None;
+        let mut resp = crate::Response::new();
+        self.read_hdr(con, buf, &mut resp, &mut auth_info)?;
+        if self.hdr_sz == 0 {
+            return Ok(RecvStateInt::Wait);
+        }
+        buf.truncate(self.hdr_sz);
+        ::std::mem::swap(&mut resp.hdrs, buf);
+
+        if resp.status == 401 {
+            if let Some(auth) = auth_info {
+                return Ok(RecvStateInt::DigestAuth(resp, auth));
+            } else if self.b.digest {
+                return Ok(RecvStateInt::BasicAuth);
+            }
+        }
+        if resp.status == 101 {
+            Ok(RecvStateInt::Response(resp, crate::ResponseBody::Streamed))
+        } else if resp.status >= 300 && resp.status < 400 {
+            Ok(RecvStateInt::Redirect(resp))
+        } else if self.b.need_chunk_parse {
+            Ok(RecvStateInt::Response(resp, crate::ResponseBody::Streamed))
+        } else {
+            Ok(RecvStateInt::Response(
+                resp,
+                crate::ResponseBody::Sized(self.body_sz),
+            ))
+        }
+    }
+
+    // Read server reply to Expect: 100-continue. Returns true if body should be sent now.
+    // If a final response is received it is kept for event_recv and dir is set to receiving.
+    fn wait_continue<C: TlsConnector>(
+        &mut self,
+        con: &mut Con,
+        cp: &mut CallParam,
+        deadline: Instant,
+    ) -> crate::Result<bool> {
+        let mut buf = ::std::mem::take(&mut self.buf_hdr);
+        let ret = self.wait_continue_do::<C>(con, cp, deadline, &mut buf);
+        self.buf_hdr = buf;
+        ret
+    }
+
+    fn wait_continue_do<C: TlsConnector>(
+        &mut self,
+        con: &mut Con,
+        cp: &mut CallParam,
+        deadline: Instant,
+        buf: &mut Vec<u8>,
+    ) -> crate::Result<bool> {
+        if con.is_signalled_rd() {
+            if let Err(e) = con.signalled::<C, Vec<u8>>(cp) {
+                con.set_to_close(true);
+                return Err(e);
+            }
+            loop {
+                let orig_len = self.reserve_space(true, buf)?;
+                match con.read(&mut buf[orig_len..]) {
+                    Ok(sz) if sz > 0 => buf.truncate(orig_len + sz),
+                    Err(ref ie) if ie.kind() == IoErrorKind::Interrupted => {
+                        buf.truncate(orig_len);
+                    }
+                    Err(ref ie) if ie.kind() == IoErrorKind::WouldBlock => {
+                        buf.truncate(orig_len);
+                        con.reg(cp.poll, Interest::READABLE)?;
+                        break;
+                    }
+                    _ => {
+                        buf.truncate(orig_len);
+                        return Err(crate::Error::Closed);
+                    }
+                }
+            }
+        }
+        loop {
+            let mut headers = [httparse::EMPTY_HEADER; 64];
+            let mut presp = ParseResp::new(&mut headers);
+            match presp.parse(buf)? {
+                httparse::Status::Complete(sz) => {
+                    let status = presp.code.unwrap_or(0);
+                    if status == 100 {
+                        buf.drain(..sz);
+                        self.dir = Dir::SendingBody(0);
+                        return Ok(true);
+                    } else if status / 100 == 1 && status != 101 {
+                        // Other informational responses are skipped.
+                        buf.drain(..sz);
+                        continue;
+                    }
+                    // Server replied without reading body, connection can not be reused.
+                    con.set_to_close(true);
+                    self.hdr_sz = 0;
+                    self.body_sz = 0;
+                    self.dir = Dir::Receiving(0, false);
+                    self.pending_recv = Some(self.read_response(con, buf)?);
+                    return Ok(false);
+                }
+                httparse::Status::Partial if Instant::now() >= deadline => {
+                    if !buf.is_empty() {
+                        // Server started a response but did not finish it in time. It may be
+                        // a final response, so sending body is not safe.
+                        con.set_to_close(true);
+                        return Err(crate::Error::TimeOut);
+                    }
+                    self.dir = Dir::SendingBody(0);
+                    return Ok(true);
+                }
+                httparse::Status::Partial => return Ok(false),
+            }
+        }
+    }
+
     fn read_hdr(
         &mut self,
         con: &mut Con,
@@ -782,6 +1101,7 @@ impl CallImpl {
                     || resp.status == 304
                     || resp.status >= 100 && resp.status < 200
                     || self.b.method == Method::HEAD
+                    || self.b.method == Method::CONNECT && resp.status / 100 == 2
                 {
                     self.body_sz = 0;
                 } else {
@@ -825,6 +1145,14 @@ impl CallImpl {
                                 resp.ws = true;
                             }
                         }
+                    } else if self.b.event_source
+                        && resp.status == 200
+                        && h.name.eq_ignore_ascii_case("content-type")
+                    {
+                        // Event stream is open ended like a WebSocket.
+                        if h.value.starts_with(b"text/event-stream") {
+                            self.b.dur = Duration::from_secs(3600 * 24 * 365);
+                        }
                     } else if resp.status == 401 && h.name.eq_ignore_ascii_case("www-authenticate")
                     {
                         if let Ok(val) = from_utf8(h.value) {
@@ -1,19 +1,19 @@
 use crate::call::CallImpl;
 use crate::resolve::{self, Dns, DnsCache};
 use crate::tls_api::{
-    hash, HandshakeError, HashType, MidHandshakeTlsStream, TlsConnector, TlsConnectorBuilder,
-    TlsStream,
+    HandshakeError, MidHandshakeTlsStream, TlsConnector, TlsConnectorBuilder, TlsStream,
+};
+use crate::types::{
+    CallBuilderImpl, CallParam, IpList, RecvStateInt, SendBuf, SendStateInt, TlsOpts,
 };
-use crate::types::{CallBuilderImpl, CallParam, IpList, RecvStateInt, SendStateInt};
 use crate::{CallRef, HttpcCfg, Result};
-use data_encoding::BASE64;
 use fxhash::FxHashMap as HashMap;
 use mio::net::TcpStream;
 use mio::{event::Source, Interest, Registry, Token};
 use slab::Slab;
 use smallvec::SmallVec;
 use std::io::ErrorKind as IoErrorKind;
-use std::io::{Read, Write};
+use std::io::{IoSlice, Read, Write};
 use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
 use std::time::{Duration, Instant};
@@ -78,7 +78,7 @@ impl Con {
             sock: None,
             dns: None,
             insecure,
-            host: ConHost::new(&cb.bytes.host, port),
+            host: ConHost::new(&cb.bytes.host, port, &cb.tls_opts),
             idle_since: Instant::now(),
             resolved: SmallVec::default(),
             is_tls: cb.tls,
@@ -146,6 +146,15 @@ impl Con {
         &self.host
     }
 
+    // Name used for SNI and certificate verification.
+    fn tls_name(&self) -> &str {
+        if let Some(ref sni) = self.host.tls_opts.sni {
+            sni
+        } else {
+            self.host.as_ref()
+        }
+    }
+
     fn update_token(&mut self, poll: &Registry, v: usize, fixed: bool) -> Result<()> {
         if !fixed {
             self.token = Token(self.token.0 + v);
@@ -340,13 +349,29 @@ impl Con {
             for rca in cp.cfg.pem_ca.iter() {
                 let _ = connector.add_pem_certificate(rca);
             }
+            connector.root_certs(cp.cfg.root_certs)?;
             if self.insecure {
                 let _ = connector.danger_accept_invalid_certs().unwrap();
             }
+            if cp.cfg.verifier.is_some() {
+                connector.defer_verification()?;
+            }
+            if let Some(v) = self.host.tls_opts.min_version {
+                connector.set_min_version(v)?;
+            }
+            if let Some(v) = self.host.tls_opts.max_version {
+                connector.set_max_version(v)?;
+            }
+            if !self.host.tls_opts.ciphers.is_empty() {
+                connector.set_ciphers(&self.host.tls_opts.ciphers)?;
+            }
+            if let Some(ref path) = cp.cfg.key_log_file {
+                connector.key_log(path)?;
+            }
             let connector = connector.build()?;
             self.reg(cp.poll, Interest::READABLE)?;
             let tcp = self.sock.take().unwrap();
-            let r = connector.connect(self.host.as_ref(), tcp);
+            let r = connector.connect(self.tls_name(), tcp);
             self.handshake_resp::<C>(r, cp.cfg)?;
         }
         if self.mid_tls.is_some() {
@@ -366,38 +391,19 @@ impl Con {
     ) -> Result<()> {
         match r {
             Ok(tls) => {
-                let mut pin_match = true;
-                for pin in cfg.pins.iter() {
-                    let host = self.host.as_ref();
-                    if pin.0.eq_ignore_ascii_case(host) {
-                        // If we found host, we now must find a pin match
-                        pin_match = false;
-                        let der = tls.peer_pubkey();
-                        for pin in pin.1.iter() {
-                            let hash_der;
-                            let prefix = if pin.starts_with("sha256/") {
-                                hash_der = hash(HashType::SHA256, &der);
-                                "sha256/"
-                            } else if pin.starts_with("sha1/") {
-                                hash_der = hash(HashType::SHA256, &der);
-                                "sha1/"
-                            } else {
-                                continue;
-                            };
-                            let mut base_buf = [0u8; 128];
-                            let base_len = BASE64.encode_len(hash_der.len());
-                            BASE64.encode_mut(&hash_der, &mut base_buf[..base_len]);
-                            let base64_der = std::str::from_utf8(&base_buf[..base_len]).unwrap();
-                            // println!("Compare {} {}", base64_der, &pin[prefix.len()..]);
-                            if base64_der.eq_ignore_ascii_case(&pin[prefix.len()..]) {
-                                pin_match = true;
-                                break;
-                            }
-                        }
+                if let Some(ref verifier) = cfg.verifier {
+                    let chain = tls.peer_chain();
+                    if let Err(reason) = verifier.verify(self.tls_name(), &chain, tls.verified()) {
+                        return Err(crate::Error::CertRejected(reason));
                     }
                 }
-                if !pin_match {
-                    return Err(crate::Error::InvalidPin);
+                if !cfg.pins.is_empty() || !cfg.pin_sets.is_empty() {
+                    crate::api::check_pins(
+                        cfg,
+                        self.tls_name(),
+                        &tls.peer_chain(),
+                        &tls.peer_pubkey(),
+                    )?;
                 }
                 self.tls = Some(tls);
             }
@@ -460,6 +466,26 @@ impl Write for Con {
         res
     }
 
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> ::std::io::Result<usize> {
+        self.readable_is_error = false;
+        let res = if let Some(ref mut tcp) = self.sock {
+            tcp.write_vectored(bufs)
+        } else if let Some(ref mut tls) = self.tls {
+            tls.write_vectored(bufs)
+        } else {
+            return Err(::std::io::Error::new(
+                ::std::io::ErrorKind::WouldBlock,
+                "No socket",
+            ));
+        };
+        if let Err(ref ie) = res {
+            if ie.kind() == IoErrorKind::WouldBlock {
+                self.set_signalled_wr(false);
+            }
+        }
+        res
+    }
+
     fn flush(&mut self) -> ::std::io::Result<()> {
         if let Some(ref mut tcp) = self.sock {
             tcp.flush()
@@ -521,17 +547,23 @@ impl Source for Con {
         }
     }
 }
+// Keep-alive key. Connections are only reused by calls with equal TLS settings.
 #[derive(Clone)]
 struct ConHost {
     host: SmallVec<[u8; 32]>,
     port: u16,
+    tls_opts: TlsOpts,
 }
 
 impl ConHost {
-    pub fn new(uri: &[u8], port: u16) -> ConHost {
+    pub fn new(uri: &[u8], port: u16, tls_opts: &TlsOpts) -> ConHost {
         let mut sv = SmallVec::new();
         sv.extend_from_slice(uri);
-        ConHost { host: sv, port }
+        ConHost {
+            host: sv,
+            port,
+            tls_opts: tls_opts.clone(),
+        }
     }
 }
 use std::hash::{Hash, Hasher};
@@ -553,7 +585,7 @@ impl ::std::convert::AsRef<str> for ConHost {
 
 impl PartialEq for ConHost {
     fn eq(&self, uri: &ConHost) -> bool {
-        self.host == uri.host
+        self.host == uri.host && self.port == uri.port && self.tls_opts == uri.tls_opts
     }
 }
 
@@ -717,7 +749,8 @@ impl ConTable {
             }
             for call in calls.as_mut().iter_mut() {
                 if !call.is_done() {
-                    if now - call.start_time() >= call.settings().dur {
+                    if now - call.start_time() >= call.settings().dur || call.ws_keepalive_due(now)
+                    {
                         out.push(CallRef::new(call.call_id()));
                     } else {
                         // if let Some(host) = call.settings().bytes.host() {
@@ -750,6 +783,17 @@ impl ConTable {
             .map(|c| c.peek_body(off))
             .unwrap_or(&[])
     }
+    pub fn set_ws_deadline(&mut self, call: &crate::Call, d: Option<Instant>) {
+        let con = call.con();
+        let tuple = if call.fixed {
+            self.cons_fixed.get_mut(&con)
+        } else {
+            self.cons.get_mut(con)
+        };
+        if let Some(c) = tuple.and_then(|t| t.1.as_mut()) {
+            c.set_ws_deadline(d);
+        }
+    }
     pub fn try_truncate(&mut self, call: &crate::Call, off: &mut usize) {
         let con = call.con();
         if call.fixed {
@@ -768,7 +812,7 @@ impl ConTable {
         &mut self,
         call: &mut crate::Call,
         cp: &mut CallParam,
-        buf: Option<&[u8]>,
+        buf: SendBuf,
     ) -> Result<SendStateInt> {
         let cons = call.cons();
         let mut con = None;
@@ -816,7 +860,10 @@ impl ConTable {
         };
         // Take CallImpl out so we can call it without borrowing issues.
         let mut call_impl = conp.1.take().unwrap();
-        let res = call_impl.event_send::<C>(&mut conp.0, cp, buf);
+        let res = match buf {
+            SendBuf::Buf(buf) => call_impl.event_send::<C>(&mut conp.0, cp, buf),
+            SendBuf::Vectored(bufs) => call_impl.event_send_vectored::<C>(&mut conp.0, cp, bufs),
+        };
         // put it back
         conp.1 = CallVariant::Call(call_impl);
         let cr = conp.1.as_ref().map(|c| c.can_retry()).unwrap_or(false);
@@ -972,9 +1019,15 @@ impl ConTable {
         Ok(())
     }
 
-    pub fn try_keepalive(&mut self, host: &[u8], port: u16, poll: &Registry) -> Option<u16> {
+    pub fn try_keepalive(
+        &mut self,
+        host: &[u8],
+        port: u16,
+        tls_opts: &TlsOpts,
+        poll: &Registry,
+    ) -> Option<u16> {
         let con_to_close;
-        let nh = ConHost::new(host, port);
+        let nh = ConHost::new(host, port, tls_opts);
         if let Some(con) = self.keepalive.remove(&nh) {
             self.cons[con as usize].0.set_idle(false);
             if self.cons[con as usize].0.reuse(poll).is_ok() {
@@ -1028,7 +1081,7 @@ impl ConTable {
                 } else {
                     self.cons[con].0.first_use_done();
                 }
-                let nh = ConHost::new(host, builder.port);
+                let nh = ConHost::new(host, builder.port, &builder.tls_opts);
                 if self.keepalive.contains_key(&nh) {
                     let mut doclose = false;
                     {
@@ -5,6 +5,7 @@ use crate::tls_api::TlsConnector;
 use crate::types::*;
 use mio::{event::Event, Interest, Registry, Token};
 use std::collections::VecDeque;
+use std::io::IoSlice;
 // use fnv::FnvHashMap as HashMap;
 use crate::{Call, CallRef, RecvState, Response, Result, SendState};
 use std::time::Instant;
@@ -41,6 +42,10 @@ impl HttpcImpl {
         r
     }
 
+    pub fn cfg(&self) -> &crate::HttpcCfg {
+        &self.cfg
+    }
+
     pub fn cfg_mut(&mut self) -> &mut crate::HttpcCfg {
         &mut self.cfg
     }
@@ -67,10 +72,19 @@ impl HttpcImpl {
         self.free_bufs.push_front(buf);
     }
 
-    pub fn call<C: TlsConnector>(&mut self, b: CallBuilderImpl, poll: &Registry) -> Result<Call> {
+    pub fn call<C: TlsConnector>(
+        &mut self,
+        mut b: CallBuilderImpl,
+        poll: &Registry,
+    ) -> Result<Call> {
+        b.apply_defaults(&self.cfg)?;
+        b.validate()?;
         let is_fixed = b.is_fixed();
         let con_id = if b.bytes.host.len() > 0 && !is_fixed {
-            if let Some(con_id) = self.cons.try_keepalive(&b.bytes.host, b.port, poll) {
+            if let Some(con_id) = self
+                .cons
+                .try_keepalive(&b.bytes.host, b.port, &b.tls_opts, poll)
+            {
                 Some(con_id)
             } else {
                 None
@@ -198,6 +212,11 @@ impl HttpcImpl {
         }
         self.cons.peek_body(call, off)
     }
+    pub fn set_ws_deadline(&mut self, call: &Call, d: Option<Instant>) {
+        if !call.is_empty() {
+            self.cons.set_ws_deadline(call, d);
+        }
+    }
     pub fn try_truncate(&mut self, call: &crate::Call, off: &mut usize) {
         if call.is_empty() {
             return;
@@ -210,6 +229,24 @@ impl HttpcImpl {
         poll: &Registry,
         call: &mut Call,
         buf: Option<&[u8]>,
+    ) -> SendState {
+        self.call_send_buf::<C>(poll, call, SendBuf::Buf(buf))
+    }
+
+    pub fn call_send_vectored<C: TlsConnector>(
+        &mut self,
+        poll: &Registry,
+        call: &mut Call,
+        bufs: &[IoSlice],
+    ) -> SendState {
+        self.call_send_buf::<C>(poll, call, SendBuf::Vectored(bufs))
+    }
+
+    fn call_send_buf<C: TlsConnector>(
+        &mut self,
+        poll: &Registry,
+        call: &mut Call,
+        buf: SendBuf,
     ) -> SendState {
         if call.is_empty() {
             return SendState::Done;
@@ -243,6 +280,7 @@ impl HttpcImpl {
             Ok(SendStateInt::WaitReqBody) => {
                 return SendState::WaitReqBody;
             }
+            Ok(SendStateInt::WaitContinue) => SendState::WaitContinue,
             Ok(SendStateInt::Retry(_err)) => {
                 let mut b = self.call_close_int(call.clone(), true);
                 call.invalidate();
@@ -398,24 +436,10 @@ impl HttpcImpl {
         let url_hash = b.url_hash();
         for h in hdrs {
             if h.is("location") {
-                if h.value.starts_with("https://") || h.value.starts_with("http://") {
-                    if let Ok(_) = b.url(h.value) {}
-                } else if h.value.len() > 0 {
-                    b.bytes.path.truncate(0);
-                    b.bytes.query.truncate(0);
-                    if h.value.as_bytes()[0] != b'/' {
-                        b.bytes.path.push(b'/');
-                    }
-                    let mut path_split = h.value.split("?");
-                    if let Some(path) = path_split.next() {
-                        b.bytes.path.extend_from_slice(path.as_bytes());
-                    }
-                    if let Some(query) = path_split.next() {
-                        if query.len() > 0 {
-                            b.bytes.query.push(b'?');
-                            b.bytes.query.extend_from_slice(query.as_bytes());
-                        }
-                    }
+                // Location is a URI reference, resolved against request URL.
+                // If it is invalid, URL is unchanged and redirect fails.
+                if !h.value.is_empty() {
+                    let _ = b.join(h.value);
                 }
                 break;
             }
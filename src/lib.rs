@@ -139,8 +139,13 @@ pub enum Error {
     #[fail(display = "Httparse error: {}", _0)]
     Httparse(#[cause] httparse::Error),
 
-    #[fail(display = "WebSocket setup failed")]
-    WebSocketFail(Response),
+    /// Server response was not a valid WebSocket handshake.
+    #[fail(display = "WebSocket setup failed: {}", _0)]
+    WebSocketFail(WsFail, Response),
+
+    /// EventSource response was not 200 with Content-Type: text/event-stream.
+    #[fail(display = "EventSource connection failed")]
+    EventSourceFail(Response),
 
     #[fail(display = "Sync call timed out")]
     TimeOut,
@@ -163,6 +168,21 @@ pub enum Error {
     /// Invalid scheme
     #[fail(display = "Invalid scheme")]
     InvalidScheme,
+    /// Method is not a valid HTTP token.
+    #[fail(display = "Invalid HTTP method")]
+    InvalidMethod,
+    /// Header name is not a valid token or value contains CR, LF or other control characters.
+    #[fail(display = "Invalid HTTP header")]
+    InvalidHeader,
+    /// Header in HttpcCfg::headers has an invalid name or value. Contains header name.
+    #[fail(display = "Invalid HttpcCfg header: {}", _0)]
+    InvalidDefaultHeader(String),
+    /// Host contains characters not allowed in a host name or IP address.
+    #[fail(display = "Invalid host")]
+    InvalidHost,
+    /// Path contains whitespace, control or non-ASCII characters.
+    #[fail(display = "Invalid path")]
+    InvalidPath,
 
     #[cfg(any(feature = "rustls", feature = "native", feature = "openssl"))]
     #[fail(display = "TLS error {}", _0)]
@@ -193,12 +213,28 @@ pub enum Error {
     /// Eror while parsing chunked stream
     #[fail(display = "Error parsing WebSocket transfer")]
     WebSocketParse,
+    /// WebSocket server violated RFC 6455. Connection was closed with code 1002 or 1007.
+    #[fail(display = "WebSocket protocol error: {}", _0)]
+    WebSocketProtocol(&'static str),
+    /// WebSocket send queue is full. Wait for WebSocket::drained.
+    #[fail(display = "WebSocket send queue full")]
+    WouldBlock,
+    /// Nothing received from WebSocket server within keepalive timeout.
+    #[fail(display = "WebSocket keepalive timed out")]
+    WebSocketTimeout,
     /// Eror while parsing chunked stream
     #[fail(display = "Error parsing WWW-Authenticate header")]
     AuthenticateParse,
 
-    #[fail(display = "Pins were configured for domain and they did not match")]
-    InvalidPin,
+    #[fail(
+        display = "Pins were configured for domain and they did not match: {}",
+        _0
+    )]
+    InvalidPin(PinFailure),
+
+    /// Server certificate was rejected by HttpcCfg::verifier.
+    #[fail(display = "Certificate rejected: {}", _0)]
+    CertRejected(String),
 
     #[fail(display = "Can not decompress gzip/deflate response")]
     DecompressionFailure,
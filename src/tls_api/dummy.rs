@@ -3,7 +3,7 @@ use std::io;
 use std::result;
 
 use crate::tls_api;
-use crate::tls_api::{Error, Result};
+use crate::tls_api::{Error, Result, RootCerts, TlsVersion};
 
 pub struct TlsConnectorBuilder;
 pub struct TlsConnector;
@@ -13,9 +13,13 @@ pub struct TlsConnector;
 
 /// Make use of the TLS implementation's crypto hashing functions.
 /// Not picking any TLS implementation as a feature means hash will not work also
-/// and will always return an empty vec.
-pub fn hash(_algo: tls_api::HashType, _data: &[u8]) -> Vec<u8> {
-    Vec::new()
+/// and will return an empty vec. Only SHA1 is always available, as it is required
+/// for the WebSocket handshake.
+pub fn hash(algo: tls_api::HashType, data: &[u8]) -> Vec<u8> {
+    match algo {
+        tls_api::HashType::SHA1 => sha1_smol::Sha1::from(data).digest().bytes().to_vec(),
+        _ => Vec::new(),
+    }
 }
 
 impl tls_api::TlsConnectorBuilder for TlsConnectorBuilder {
@@ -42,6 +46,30 @@ impl tls_api::TlsConnectorBuilder for TlsConnectorBuilder {
     fn danger_accept_invalid_certs(&mut self) -> Result<&mut Self> {
         Err(Error::Other("No TLS"))
     }
+
+    fn defer_verification(&mut self) -> Result<&mut Self> {
+        Err(Error::Other("No TLS"))
+    }
+
+    fn set_min_version(&mut self, _v: TlsVersion) -> Result<&mut Self> {
+        Err(Error::Other("No TLS"))
+    }
+
+    fn set_max_version(&mut self, _v: TlsVersion) -> Result<&mut Self> {
+        Err(Error::Other("No TLS"))
+    }
+
+    fn set_ciphers(&mut self, _ciphers: &[String]) -> Result<&mut Self> {
+        Err(Error::Other("No TLS"))
+    }
+
+    fn root_certs(&mut self, _roots: RootCerts) -> Result<&mut Self> {
+        Err(Error::Other("No TLS"))
+    }
+
+    fn key_log(&mut self, _path: &str) -> Result<&mut Self> {
+        Err(Error::Other("No TLS"))
+    }
 }
 
 impl tls_api::TlsConnector for TlsConnector {
@@ -24,10 +24,11 @@ pub use self::dummy::hash;
 use std::fmt;
 use std::io;
 // use std::error;
-use crate::{Error, Result};
+use crate::{Error, Result, RootCerts, TlsVersion};
 use std::result;
 
 #[allow(dead_code)]
+#[derive(Clone, Copy)]
 pub enum HashType {
     MD5,
     SHA1,
@@ -45,6 +46,12 @@ pub trait TlsStreamImpl<S>: io::Read + io::Write + fmt::Debug + Send + Sync + 's
     fn peer_pubkey(&self) -> Vec<u8>;
 
     fn peer_certificate(&self) -> Vec<u8>;
+
+    /// Certificate chain in der format, starting with end-entity certificate.
+    fn peer_chain(&self) -> Vec<Vec<u8>>;
+
+    /// Result of backend certificate verification if it was deferred.
+    fn verified(&self) -> bool;
 }
 
 /// Since Rust has no HKT, it is not possible to declare something like
@@ -92,6 +99,14 @@ impl<S: 'static> TlsStream<S> {
         // cert_pubkey(self.0.peer_certificate())
         v
     }
+
+    pub fn peer_chain(&self) -> Vec<Vec<u8>> {
+        self.0.peer_chain()
+    }
+
+    pub fn verified(&self) -> bool {
+        self.0.verified()
+    }
 }
 
 #[cfg(not(any(target_os = "macos", target_os = "ios")))]
@@ -115,6 +130,10 @@ impl<S> io::Write for TlsStream<S> {
         self.0.write(buf)
     }
 
+    fn write_vectored(&mut self, bufs: &[io::IoSlice]) -> io::Result<usize> {
+        self.0.write_vectored(bufs)
+    }
+
     fn flush(&mut self) -> io::Result<()> {
         self.0.flush()
     }
@@ -165,6 +184,23 @@ pub trait TlsConnectorBuilder: Sized + Sync + Send + 'static {
 
     fn danger_accept_invalid_certs(&mut self) -> Result<&mut Self>;
 
+    /// Do not fail handshake if certificate verification fails, only remember the result.
+    /// Used when HttpcCfg::verifier makes the final decision.
+    fn defer_verification(&mut self) -> Result<&mut Self>;
+
+    fn set_min_version(&mut self, v: TlsVersion) -> Result<&mut Self>;
+    fn set_max_version(&mut self, v: TlsVersion) -> Result<&mut Self>;
+
+    /// Restrict cipher suites. Names are backend specific.
+    fn set_ciphers(&mut self, ciphers: &[String]) -> Result<&mut Self>;
+
+    /// Select root certificates besides the added ones.
+    /// Backends without bundled certificates always use the system store.
+    fn root_certs(&mut self, roots: RootCerts) -> Result<&mut Self>;
+
+    /// Append session secrets to file in NSS key log format.
+    fn key_log(&mut self, path: &str) -> Result<&mut Self>;
+
     fn build(self) -> Result<Self::Connector>;
 }
 
@@ -3,7 +3,7 @@ use std::fmt;
 use std::io;
 use std::result;
 
-use crate::tls_api::{self, Error, HashType, Result};
+use crate::tls_api::{self, Error, HashType, Result, RootCerts, TlsVersion};
 use native_tls;
 
 pub fn hash(algo: HashType, data: &[u8]) -> Vec<u8> {
@@ -39,10 +39,9 @@ impl tls_api::TlsConnectorBuilder for TlsConnectorBuilder {
     }
 
     fn add_pem_certificate(&mut self, cert: &[u8]) -> Result<&mut Self> {
-        let cert = native_tls::Certificate::from_pem(cert)?;
-
-        self.0.add_root_certificate(cert);
-
+        for cert in native_tls::Certificate::stack_from_pem(cert)? {
+            self.0.add_root_certificate(cert);
+        }
         Ok(self)
     }
 
@@ -53,11 +52,52 @@ impl tls_api::TlsConnectorBuilder for TlsConnectorBuilder {
         Ok(self)
     }
 
+    fn defer_verification(&mut self) -> Result<&mut Self> {
+        // native-tls can not report verification result of an accepted certificate.
+        // Verification stays on and verifier is called for valid certificates only.
+        Ok(self)
+    }
+
+    fn set_min_version(&mut self, v: TlsVersion) -> Result<&mut Self> {
+        self.0.min_protocol_version(Some(protocol(v)));
+        Ok(self)
+    }
+
+    fn set_max_version(&mut self, v: TlsVersion) -> Result<&mut Self> {
+        self.0.max_protocol_version(Some(protocol(v)));
+        Ok(self)
+    }
+
+    fn set_ciphers(&mut self, _ciphers: &[String]) -> Result<&mut Self> {
+        Err(Error::Other(
+            "native-tls does not support choosing cipher suites",
+        ))
+    }
+
+    fn root_certs(&mut self, _roots: RootCerts) -> Result<&mut Self> {
+        // Always uses the system store.
+        Ok(self)
+    }
+
+    fn key_log(&mut self, _path: &str) -> Result<&mut Self> {
+        // Not supported by native-tls. It is a debugging aid, so do not fail connections.
+        Ok(self)
+    }
+
     fn build(self) -> Result<TlsConnector> {
         self.0.build().map(TlsConnector).map_err(From::from)
     }
 }
 
+fn protocol(v: TlsVersion) -> native_tls::Protocol {
+    match v {
+        TlsVersion::Tls10 => native_tls::Protocol::Tlsv10,
+        TlsVersion::Tls11 => native_tls::Protocol::Tlsv11,
+        TlsVersion::Tls12 => native_tls::Protocol::Tlsv12,
+        TlsVersion::Tls13 => native_tls::Protocol::Tlsv13,
+    }
+}
+
 #[derive(Debug)]
 struct TlsStream<S: io::Read + io::Write + fmt::Debug>(native_tls::TlsStream<S>);
 
@@ -106,6 +146,19 @@ impl<S: io::Read + io::Write + fmt::Debug + Send + Sync + 'static> tls_api::TlsS
     fn peer_pubkey(&self) -> Vec<u8> {
         Vec::new()
     }
+
+    fn peer_chain(&self) -> Vec<Vec<u8>> {
+        let cert = tls_api::TlsStreamImpl::peer_certificate(self);
+        if cert.is_empty() {
+            Vec::new()
+        } else {
+            vec![cert]
+        }
+    }
+
+    fn verified(&self) -> bool {
+        true
+    }
 }
 
 struct MidHandshakeTlsStream<S: io::Read + io::Write + 'static>(
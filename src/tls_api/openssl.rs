@@ -1,14 +1,26 @@
 use std::fmt;
+use std::fs::OpenOptions;
 use std::io;
+use std::io::Write;
 use std::result;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 
 // use super::tls_api;
-use crate::tls_api::{self, Error, HashType, Result};
+use crate::tls_api::{self, HashType, Result, RootCerts, TlsVersion};
 use openssl;
 use openssl::hash::{hash as hashf, MessageDigest};
 
-pub struct TlsConnectorBuilder(pub openssl::ssl::SslConnectorBuilder, bool);
-pub struct TlsConnector(pub openssl::ssl::SslConnector, bool);
+pub struct TlsConnectorBuilder(
+    pub openssl::ssl::SslConnectorBuilder,
+    bool,
+    Option<Arc<AtomicBool>>,
+);
+pub struct TlsConnector(
+    pub openssl::ssl::SslConnector,
+    bool,
+    Option<Arc<AtomicBool>>,
+);
 
 // pub struct TlsAcceptorBuilder(pub openssl::ssl::SslAcceptorBuilder);
 // pub struct TlsAcceptor(pub openssl::ssl::SslAcceptor);
@@ -48,8 +60,10 @@ impl tls_api::TlsConnectorBuilder for TlsConnectorBuilder {
     }
 
     fn add_pem_certificate(&mut self, cert: &[u8]) -> Result<&mut Self> {
-        let cert = openssl::x509::X509::from_pem(cert)?;
-        self.0.cert_store_mut().add_cert(cert)?;
+        for cert in openssl::x509::X509::stack_from_pem(cert)? {
+            // Duplicates in bundles are not an error.
+            let _ = self.0.cert_store_mut().add_cert(cert);
+        }
         Ok(self)
     }
 
@@ -58,8 +72,65 @@ impl tls_api::TlsConnectorBuilder for TlsConnectorBuilder {
         Ok(self)
     }
 
+    fn defer_verification(&mut self) -> Result<&mut Self> {
+        let ok = Arc::new(AtomicBool::new(true));
+        let cb_ok = ok.clone();
+        self.0
+            .set_verify_callback(openssl::ssl::SslVerifyMode::PEER, move |preverify, _| {
+                if !preverify {
+                    cb_ok.store(false, Ordering::Relaxed);
+                }
+                true
+            });
+        self.2 = Some(ok);
+        Ok(self)
+    }
+
+    fn set_min_version(&mut self, v: TlsVersion) -> Result<&mut Self> {
+        self.0.set_min_proto_version(Some(ssl_version(v)))?;
+        Ok(self)
+    }
+
+    fn set_max_version(&mut self, v: TlsVersion) -> Result<&mut Self> {
+        self.0.set_max_proto_version(Some(ssl_version(v)))?;
+        Ok(self)
+    }
+
+    fn set_ciphers(&mut self, ciphers: &[String]) -> Result<&mut Self> {
+        // OpenSSL configures TLS 1.3 suites separately, their names start with TLS_.
+        let (v13, v12): (Vec<&str>, Vec<&str>) = ciphers
+            .iter()
+            .map(|c| c.as_str())
+            .partition(|c| c.starts_with("TLS_"));
+        // Empty TLS 1.3 list disables TLS 1.3.
+        self.0.set_ciphersuites(&v13.join(":"))?;
+        if v12.is_empty() {
+            self.0
+                .set_min_proto_version(Some(openssl::ssl::SslVersion::TLS1_3))?;
+        } else {
+            self.0.set_cipher_list(&v12.join(":"))?;
+        }
+        Ok(self)
+    }
+
+    fn root_certs(&mut self, _roots: RootCerts) -> Result<&mut Self> {
+        // Always uses the system store, which honours SSL_CERT_FILE and SSL_CERT_DIR.
+        Ok(self)
+    }
+
+    fn key_log(&mut self, path: &str) -> Result<&mut Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let file = Mutex::new(file);
+        self.0.set_keylog_callback(move |_, line| {
+            if let Ok(mut file) = file.lock() {
+                let _ = writeln!(file, "{}", line);
+            }
+        });
+        Ok(self)
+    }
+
     fn build(self) -> Result<TlsConnector> {
-        Ok(TlsConnector(self.0.build(), self.1))
+        Ok(TlsConnector(self.0.build(), self.1, self.2))
     }
 }
 
@@ -69,8 +140,20 @@ impl TlsConnectorBuilder {
     }
 }
 
+fn ssl_version(v: TlsVersion) -> openssl::ssl::SslVersion {
+    match v {
+        TlsVersion::Tls10 => openssl::ssl::SslVersion::TLS1,
+        TlsVersion::Tls11 => openssl::ssl::SslVersion::TLS1_1,
+        TlsVersion::Tls12 => openssl::ssl::SslVersion::TLS1_2,
+        TlsVersion::Tls13 => openssl::ssl::SslVersion::TLS1_3,
+    }
+}
+
 #[derive(Debug)]
-struct TlsStream<S: io::Read + io::Write + fmt::Debug>(openssl::ssl::SslStream<S>);
+struct TlsStream<S: io::Read + io::Write + fmt::Debug>(
+    openssl::ssl::SslStream<S>,
+    Option<Arc<AtomicBool>>,
+);
 
 impl<S: io::Read + io::Write + fmt::Debug> TlsStream<S> {}
 
@@ -130,10 +213,30 @@ impl<S: io::Read + io::Write + fmt::Debug + Send + Sync + 'static> tls_api::TlsS
         }
         Vec::new()
     }
+
+    fn peer_chain(&self) -> Vec<Vec<u8>> {
+        let mut out = Vec::new();
+        if let Some(chain) = self.0.ssl().peer_cert_chain() {
+            for cert in chain.iter() {
+                if let Ok(der) = cert.to_der() {
+                    out.push(der);
+                }
+            }
+        }
+        out
+    }
+
+    fn verified(&self) -> bool {
+        self.1
+            .as_ref()
+            .map(|v| v.load(Ordering::Relaxed))
+            .unwrap_or(true)
+    }
 }
 
 struct MidHandshakeTlsStream<S: io::Read + io::Write + 'static>(
     Option<openssl::ssl::MidHandshakeSslStream<S>>,
+    Option<Arc<AtomicBool>>,
 );
 
 impl<S: io::Read + io::Write> fmt::Debug for MidHandshakeTlsStream<S> {
@@ -146,16 +249,20 @@ impl<S: io::Read + io::Write + fmt::Debug + Send + Sync + 'static>
     tls_api::MidHandshakeTlsStreamImpl<S> for MidHandshakeTlsStream<S>
 {
     fn handshake(&mut self) -> result::Result<tls_api::TlsStream<S>, tls_api::HandshakeError<S>> {
+        let verified = self.1.clone();
         self.0
             .take()
             .unwrap()
             .handshake()
-            .map(|s| tls_api::TlsStream::new(TlsStream(s)))
-            .map_err(map_handshake_error)
+            .map(|s| tls_api::TlsStream::new(TlsStream(s, verified.clone())))
+            .map_err(|e| map_handshake_error(e, verified))
     }
 }
 
-fn map_handshake_error<S>(e: openssl::ssl::HandshakeError<S>) -> tls_api::HandshakeError<S>
+fn map_handshake_error<S>(
+    e: openssl::ssl::HandshakeError<S>,
+    verified: Option<Arc<AtomicBool>>,
+) -> tls_api::HandshakeError<S>
 where
     S: io::Read + io::Write + fmt::Debug + Send + Sync + 'static,
 {
@@ -167,7 +274,7 @@ where
             tls_api::HandshakeError::Failure(From::from(e.into_error()))
         }
         openssl::ssl::HandshakeError::WouldBlock(s) => tls_api::HandshakeError::Interrupted(
-            tls_api::MidHandshakeTlsStream::new(MidHandshakeTlsStream(Some(s))),
+            tls_api::MidHandshakeTlsStream::new(MidHandshakeTlsStream(Some(s), verified)),
         ),
     }
 }
@@ -177,7 +284,7 @@ impl tls_api::TlsConnector for TlsConnector {
 
     fn builder() -> Result<TlsConnectorBuilder> {
         openssl::ssl::SslConnector::builder(openssl::ssl::SslMethod::tls())
-            .map(|v| TlsConnectorBuilder(v, false))
+            .map(|v| TlsConnectorBuilder(v, false, None))
             .map_err(From::from)
     }
 
@@ -201,14 +308,14 @@ impl tls_api::TlsConnector for TlsConnector {
                 }
             };
             match cfgr {
-                Ok(c) => Ok(tls_api::TlsStream::new(TlsStream(c))),
-                Err(e) => Err(map_handshake_error(e)),
+                Ok(c) => Ok(tls_api::TlsStream::new(TlsStream(c, self.2.clone()))),
+                Err(e) => Err(map_handshake_error(e, self.2.clone())),
             }
         } else {
             self.0
                 .connect(domain, stream)
-                .map(|s| tls_api::TlsStream::new(TlsStream(s)))
-                .map_err(map_handshake_error)
+                .map(|s| tls_api::TlsStream::new(TlsStream(s, self.2.clone())))
+                .map_err(|e| map_handshake_error(e, self.2.clone()))
         }
     }
 }
@@ -1,15 +1,17 @@
+use data_encoding::HEXLOWER;
 use ring::digest;
 use rustls::{self, Certificate};
-use std::cell::RefCell;
 use std::fmt;
+use std::fs::{File, OpenOptions};
 use std::io;
+use std::io::Write;
 use std::result;
 use std::str;
-use std::sync::Arc;
-use webpki;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
 use webpki_roots;
 
-use crate::tls_api::{self, Error, HashType, Result};
+use crate::tls_api::{self, Error, HashType, Result, RootCerts, TlsVersion};
 
 pub fn hash(algo: HashType, data: &[u8]) -> Vec<u8> {
     let mut hasher = match algo {
@@ -30,7 +32,7 @@ pub fn hash(algo: HashType, data: &[u8]) -> Vec<u8> {
 // thread_local!(static CLIENT_CFG: RefCell<Arc<rustls::ClientConfig>> = RefCell::new(Arc::new(rustls::ClientConfig::new())));
 // thread_local!(static CLIENT_CFG_SEALED: RefCell<bool> = RefCell::new(false));
 
-pub struct TlsConnector(Arc<rustls::ClientConfig>);
+pub struct TlsConnector(Arc<rustls::ClientConfig>, Option<Arc<AtomicBool>>);
 
 // pub struct TlsAcceptorBuilder(rustls::ServerConfig);
 // pub struct TlsAcceptor(Arc<rustls::ServerConfig>);
@@ -43,6 +45,8 @@ where
     session: rustls::ClientConnection,
     // Amount of data buffered in session
     write_skip: usize,
+    // Set if verification was deferred
+    verified: Option<Arc<AtomicBool>>,
 }
 
 // TODO: do not require Sync from TlsStream
@@ -186,6 +190,15 @@ where
         Ok(len)
     }
 
+    fn write_vectored(&mut self, bufs: &[io::IoSlice]) -> io::Result<usize> {
+        self.complete_prior_io()?;
+
+        let len = self.session.writer().write_vectored(bufs)?;
+        let _ = self.session.complete_io(&mut self.stream);
+
+        Ok(len)
+    }
+
     fn flush(&mut self) -> io::Result<()> {
         self.session.writer().flush()?;
         self.session.write_tls(&mut self.stream)?;
@@ -222,6 +235,20 @@ where
     fn peer_pubkey(&self) -> Vec<u8> {
         Vec::new()
     }
+
+    fn peer_chain(&self) -> Vec<Vec<u8>> {
+        self.session
+            .peer_certificates()
+            .map(|certs| certs.iter().map(|c| c.0.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    fn verified(&self) -> bool {
+        self.verified
+            .as_ref()
+            .map(|v| v.load(Ordering::Relaxed))
+            .unwrap_or(true)
+    }
 }
 
 // MidHandshakeTlsStream
@@ -257,6 +284,88 @@ where
 pub struct TlsConnectorBuilder {
     ders: Vec<Vec<u8>>,
     accept_invalid: bool,
+    defer_verify: bool,
+    min_version: Option<TlsVersion>,
+    max_version: Option<TlsVersion>,
+    ciphers: Vec<String>,
+    key_log: Option<Arc<FileKeyLog>>,
+    roots: RootCerts,
+}
+
+// Loading system store is expensive, do it once.
+static SYSTEM_ROOTS: OnceLock<Vec<Vec<u8>>> = OnceLock::new();
+
+fn system_roots() -> &'static [Vec<u8>] {
+    SYSTEM_ROOTS.get_or_init(|| {
+        // Honours SSL_CERT_FILE.
+        let mut out: Vec<Vec<u8>> = rustls_native_certs::load_native_certs()
+            .map(|certs| certs.into_iter().map(|c| c.0).collect())
+            .unwrap_or_default();
+        if let Some(dirs) = std::env::var_os("SSL_CERT_DIR") {
+            for dir in std::env::split_paths(&dirs) {
+                let rd = match std::fs::read_dir(dir) {
+                    Ok(rd) => rd,
+                    _ => continue,
+                };
+                for de in rd.flatten() {
+                    if let Ok(file) = File::open(de.path()) {
+                        let mut rd = io::BufReader::new(file);
+                        out.extend(rustls_pemfile::certs(&mut rd).unwrap_or_default());
+                    }
+                }
+            }
+        }
+        out
+    })
+}
+
+// Writes secrets in NSS key log format.
+struct FileKeyLog(Mutex<File>);
+
+impl rustls::KeyLog for FileKeyLog {
+    fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+        if let Ok(mut file) = self.0.lock() {
+            let _ = writeln!(
+                file,
+                "{} {} {}",
+                label,
+                HEXLOWER.encode(client_random),
+                HEXLOWER.encode(secret)
+            );
+        }
+    }
+}
+
+// Runs default verification but only records the result.
+struct DeferredVerifier {
+    inner: rustls::client::WebPkiVerifier,
+    ok: Arc<AtomicBool>,
+}
+
+impl rustls::client::ServerCertVerifier for DeferredVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &rustls::client::ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: std::time::SystemTime,
+    ) -> result::Result<rustls::client::ServerCertVerified, rustls::Error> {
+        let ok = self
+            .inner
+            .verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                scts,
+                ocsp_response,
+                now,
+            )
+            .is_ok();
+        self.ok.store(ok, Ordering::Relaxed);
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
 }
 impl tls_api::TlsConnectorBuilder for TlsConnectorBuilder {
     type Connector = TlsConnector;
@@ -288,24 +397,109 @@ impl tls_api::TlsConnectorBuilder for TlsConnectorBuilder {
         Ok(self)
     }
 
+    fn defer_verification(&mut self) -> Result<&mut Self> {
+        self.defer_verify = true;
+        Ok(self)
+    }
+
+    fn set_min_version(&mut self, v: TlsVersion) -> Result<&mut Self> {
+        self.min_version = Some(v);
+        Ok(self)
+    }
+
+    fn set_max_version(&mut self, v: TlsVersion) -> Result<&mut Self> {
+        self.max_version = Some(v);
+        Ok(self)
+    }
+
+    fn set_ciphers(&mut self, ciphers: &[String]) -> Result<&mut Self> {
+        self.ciphers = ciphers.to_vec();
+        Ok(self)
+    }
+
+    fn root_certs(&mut self, roots: RootCerts) -> Result<&mut Self> {
+        self.roots = roots;
+        Ok(self)
+    }
+
+    fn key_log(&mut self, path: &str) -> Result<&mut Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        self.key_log = Some(Arc::new(FileKeyLog(Mutex::new(file))));
+        Ok(self)
+    }
+
     fn build(mut self) -> Result<TlsConnector> {
         let mut root_store = rustls::RootCertStore::empty();
         if self.ders.len() > 0 {
             root_store.add_parsable_certificates(&self.ders);
         }
-        root_store.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
-            rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
-                ta.subject,
-                ta.spki,
-                ta.name_constraints,
-            )
-        }));
+        if self.roots != RootCerts::Bundled {
+            root_store.add_parsable_certificates(system_roots());
+        }
+        if self.roots != RootCerts::System {
+            root_store.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(
+                |ta| {
+                    rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+                        ta.subject,
+                        ta.spki,
+                        ta.name_constraints,
+                    )
+                },
+            ));
+        }
 
+        let verify_roots = if self.defer_verify {
+            Some(root_store.clone())
+        } else {
+            None
+        };
+        // rustls only implements TLS 1.2 and 1.3
+        let min = self.min_version.unwrap_or(TlsVersion::Tls12);
+        let max = self.max_version.unwrap_or(TlsVersion::Tls13);
+        let mut versions = Vec::new();
+        if min <= TlsVersion::Tls12 && max >= TlsVersion::Tls12 {
+            versions.push(&rustls::version::TLS12);
+        }
+        if max >= TlsVersion::Tls13 {
+            versions.push(&rustls::version::TLS13);
+        }
+        if versions.is_empty() {
+            return Err(Error::Other("rustls only supports TLS 1.2 and 1.3"));
+        }
+        let suites: Vec<rustls::SupportedCipherSuite> = if self.ciphers.is_empty() {
+            rustls::DEFAULT_CIPHER_SUITES.to_vec()
+        } else {
+            rustls::ALL_CIPHER_SUITES
+                .iter()
+                .filter(|cs| {
+                    let name = format!("{:?}", cs.suite());
+                    self.ciphers.iter().any(|c| c.eq_ignore_ascii_case(&name))
+                })
+                .cloned()
+                .collect()
+        };
+        if suites.is_empty() {
+            return Err(Error::Other("None of the cipher suites are supported"));
+        }
         let mut cfg = rustls::ClientConfig::builder()
-            .with_safe_defaults()
+            .with_cipher_suites(&suites)
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&versions)?
             .with_root_certificates(root_store)
             .with_no_client_auth();
-        if self.accept_invalid {
+        if let Some(key_log) = self.key_log.take() {
+            cfg.key_log = key_log;
+        }
+        let mut verified = None;
+        if let Some(roots) = verify_roots {
+            let ok = Arc::new(AtomicBool::new(false));
+            cfg.dangerous()
+                .set_certificate_verifier(Arc::new(DeferredVerifier {
+                    inner: rustls::client::WebPkiVerifier::new(roots, None),
+                    ok: ok.clone(),
+                }));
+            verified = Some(ok);
+        } else if self.accept_invalid {
             struct NoCertificateVerifier;
 
             impl rustls::client::ServerCertVerifier for NoCertificateVerifier {
@@ -328,7 +522,7 @@ impl tls_api::TlsConnectorBuilder for TlsConnectorBuilder {
         }
 
         let cfg = Arc::new(cfg);
-        Ok(TlsConnector(cfg))
+        Ok(TlsConnector(cfg, verified))
     }
 }
 
@@ -358,6 +552,7 @@ impl tls_api::TlsConnector for TlsConnector {
             session: rustls::ClientConnection::new(self.0.clone(), domain)
                 .map_err(|e| tls_api::HandshakeError::Failure(Error::Other("invalid domain")))?,
             write_skip: 0,
+            verified: self.1.clone(),
         };
         // tls_stream.session.set_buffer_limit(16 * 1024);
         tls_stream.complete_handleshake_mid()
@@ -1,11 +1,18 @@
 use crate::httpc::HttpcImpl;
 use crate::resolve::DnsCache;
 use crate::tls_api::TlsConnector;
+use flate2::write::{DeflateEncoder, GzEncoder};
+use flate2::Compression;
 use mio::Registry;
-use percent_encoding::{percent_encode, utf8_percent_encode, AsciiSet, CONTROLS};
+use percent_encoding::{percent_encode, utf8_percent_encode, AsciiSet, CONTROLS, NON_ALPHANUMERIC};
 use pest::Parser;
 use smallvec::SmallVec;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, IoSlice, Read, Write};
+use std::path;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use std::{hash::Hasher, ops::Deref};
 use url::Url;
@@ -13,6 +20,13 @@ use url::Url;
 const FRAGMENT: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'<').add(b'>').add(b'`');
 const PATH_SEGMENT_ENCODE_SET: &AsciiSet = &FRAGMENT.add(b'#').add(b'?').add(b'{').add(b'}');
 const QUERY_ENCODE_SET: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'#').add(b'<').add(b'>');
+// application/x-www-form-urlencoded, space is replaced with +.
+const FORM_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'*')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b' ');
 const USERINFO_ENCODE_SET: &AsciiSet = &PATH_SEGMENT_ENCODE_SET
     .add(b'/')
     .add(b':')
@@ -40,10 +54,18 @@ pub(crate) enum RecvStateInt {
     Retry(crate::Error),
 }
 
+/// Data passed to ConTable::event_send.
+pub enum SendBuf<'a, 'b> {
+    Buf(Option<&'a [u8]>),
+    /// Only for upgraded connections.
+    Vectored(&'a [IoSlice<'b>]),
+}
+
 #[derive(Debug)]
 pub enum SendStateInt {
     SentBody(usize),
     WaitReqBody,
+    WaitContinue,
     Receiving,
     Done,
     Wait,
@@ -367,6 +389,7 @@ impl CallBytes {
 //     }
 // }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Method {
     GET,
@@ -375,6 +398,11 @@ pub enum Method {
     DELETE,
     OPTIONS,
     HEAD,
+    PATCH,
+    TRACE,
+    CONNECT,
+    /// Extension method token like WebDAV PROPFIND. Sent as is.
+    Custom(String),
 }
 impl Default for Method {
     fn default() -> Method {
@@ -382,8 +410,8 @@ impl Default for Method {
     }
 }
 impl Method {
-    fn from_str(s: &str) -> Method {
-        if s.eq_ignore_ascii_case("get") {
+    fn from_str(s: &str) -> crate::Result<Method> {
+        let m = if s.eq_ignore_ascii_case("get") {
             Method::GET
         } else if s.eq_ignore_ascii_case("post") {
             Method::POST
@@ -395,11 +423,20 @@ impl Method {
             Method::OPTIONS
         } else if s.eq_ignore_ascii_case("head") {
             Method::HEAD
+        } else if s.eq_ignore_ascii_case("patch") {
+            Method::PATCH
+        } else if s.eq_ignore_ascii_case("trace") {
+            Method::TRACE
+        } else if s.eq_ignore_ascii_case("connect") {
+            Method::CONNECT
+        } else if is_token(s) {
+            Method::Custom(s.to_string())
         } else {
-            Method::GET
-        }
+            return Err(crate::Error::InvalidMethod);
+        };
+        Ok(m)
     }
-    pub fn as_str(&self) -> &'static str {
+    pub fn as_str(&self) -> &str {
         match *self {
             Method::GET => "GET",
             Method::POST => "POST",
@@ -407,10 +444,48 @@ impl Method {
             Method::DELETE => "DELETE",
             Method::OPTIONS => "OPTIONS",
             Method::HEAD => "HEAD",
+            Method::PATCH => "PATCH",
+            Method::TRACE => "TRACE",
+            Method::CONNECT => "CONNECT",
+            Method::Custom(ref m) => m,
         }
     }
 }
 
+// RFC 7230 token.
+fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|c| c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&c))
+}
+
+// RFC 7230 field-value: visible characters, obs-text, space and tab.
+pub(crate) fn is_field_value(s: &str) -> bool {
+    s.bytes().all(|c| c == b'\t' || (c >= b' ' && c != 0x7f))
+}
+
+// RFC 3986 reg-name or IP literal.
+fn is_host(b: &[u8]) -> bool {
+    b.iter()
+        .all(|c| c.is_ascii_alphanumeric() || b"-._~!$&'()*+,;=%:[]".contains(c))
+}
+
+// Length of first "name: value\r\n" line in b including CRLF.
+fn header_line(b: &[u8]) -> Option<usize> {
+    b.windows(2).position(|w| w == b"\r\n").map(|i| i + 2)
+}
+
+fn header_is(line: &[u8], key: &str) -> bool {
+    line.len() > key.len()
+        && line[key.len()] == b':'
+        && line[..key.len()].eq_ignore_ascii_case(key.as_bytes())
+}
+
+// Path part of request-target. Query may be included.
+fn is_path(b: &[u8]) -> bool {
+    b.iter().all(|&c| c > b' ' && c < 0x7f && c != b'#')
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum TransferEncoding {
     Identity,
@@ -422,6 +497,195 @@ impl Default for TransferEncoding {
     }
 }
 
+/// Part of a request body streamed by CallImpl instead of being held in CallBuilderImpl::body.
+#[derive(Debug, Clone)]
+pub(crate) enum BodyPart {
+    Bytes(Vec<u8>),
+    /// File path and number of bytes to send from it.
+    File(path::PathBuf, u64),
+    /// Reader and its length if known.
+    Reader(SourceReader, Option<u64>),
+}
+
+impl BodyPart {
+    /// None if length is not known until reader is exhausted.
+    pub fn len(&self) -> Option<u64> {
+        match *self {
+            BodyPart::Bytes(ref b) => Some(b.len() as u64),
+            BodyPart::File(_, len) => Some(len),
+            BodyPart::Reader(_, len) => len,
+        }
+    }
+}
+
+/// Reader of a BodySource. Shared between clones of CallBuilderImpl,
+/// it can only be read by one request.
+#[derive(Clone)]
+pub(crate) struct SourceReader(Arc<Mutex<(Box<dyn Read + Send>, bool)>>);
+
+impl SourceReader {
+    pub fn new(r: Box<dyn Read + Send>) -> SourceReader {
+        SourceReader(Arc::new(Mutex::new((r, false))))
+    }
+}
+
+impl fmt::Debug for SourceReader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SourceReader")
+    }
+}
+
+/// Reads BodyPart list sequentially. Files are opened when reached.
+#[derive(Default)]
+pub(crate) struct BodyReader {
+    part: usize,
+    // Position within current part.
+    pos: u64,
+    file: Option<File>,
+}
+
+impl BodyReader {
+    /// Append up to max bytes to out. Returns 0 once all parts are read.
+    pub fn read(&mut self, parts: &[BodyPart], out: &mut Vec<u8>, max: usize) -> io::Result<usize> {
+        while let Some(part) = parts.get(self.part) {
+            let left = match part.len() {
+                Some(len) => (len - self.pos).min(max as u64) as usize,
+                None => max,
+            };
+            if left == 0 {
+                self.next_part();
+                continue;
+            }
+            let start = out.len();
+            let n = match *part {
+                BodyPart::Bytes(ref b) => {
+                    let pos = self.pos as usize;
+                    out.extend_from_slice(&b[pos..pos + left]);
+                    left
+                }
+                BodyPart::File(ref path, _) => {
+                    if self.file.is_none() {
+                        self.file = Some(File::open(path)?);
+                    }
+                    out.resize(start + left, 0);
+                    self.file.as_mut().unwrap().read(&mut out[start..])?
+                }
+                BodyPart::Reader(ref r, _) => {
+                    let mut r = r.0.lock().unwrap();
+                    if self.pos == 0 {
+                        if r.1 {
+                            return Err(io::Error::other(
+                                "body source already sent, it can not be sent again",
+                            ));
+                        }
+                        r.1 = true;
+                    }
+                    out.resize(start + left, 0);
+                    r.0.read(&mut out[start..])?
+                }
+            };
+            out.truncate(start + n);
+            if n == 0 {
+                if part.len().is_none() {
+                    self.next_part();
+                    continue;
+                }
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "body source shorter than its length at request start",
+                ));
+            }
+            self.pos += n as u64;
+            return Ok(n);
+        }
+        Ok(0)
+    }
+
+    fn next_part(&mut self) {
+        self.part += 1;
+        self.pos = 0;
+        self.file = None;
+    }
+}
+
+/// Compresses request body for CallBuilder::compress_body.
+pub(crate) enum BodyEncoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(DeflateEncoder<Vec<u8>>),
+}
+
+impl BodyEncoder {
+    pub fn new(enc: crate::ContentEncoding) -> BodyEncoder {
+        match enc {
+            crate::ContentEncoding::Gzip => {
+                BodyEncoder::Gzip(GzEncoder::new(Vec::new(), Compression::default()))
+            }
+            crate::ContentEncoding::Deflate => {
+                BodyEncoder::Deflate(DeflateEncoder::new(Vec::new(), Compression::default()))
+            }
+        }
+    }
+
+    /// Compress entire body.
+    pub fn encode(enc: crate::ContentEncoding, b: &[u8]) -> io::Result<Vec<u8>> {
+        let mut e = Self::new(enc);
+        e.write(b)?;
+        e.finish()
+    }
+
+    /// Compress b, compressed output is collected with take.
+    pub fn write(&mut self, b: &[u8]) -> io::Result<()> {
+        match *self {
+            BodyEncoder::Gzip(ref mut e) => e.write_all(b),
+            BodyEncoder::Deflate(ref mut e) => e.write_all(b),
+        }
+    }
+
+    /// Move compressed output so far to out.
+    pub fn take(&mut self, out: &mut Vec<u8>) {
+        let v = match *self {
+            BodyEncoder::Gzip(ref mut e) => e.get_mut(),
+            BodyEncoder::Deflate(ref mut e) => e.get_mut(),
+        };
+        out.append(v);
+    }
+
+    /// Rest of compressed output.
+    pub fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            BodyEncoder::Gzip(e) => e.finish(),
+            BodyEncoder::Deflate(e) => e.finish(),
+        }
+    }
+}
+
+/// Per call TLS settings. Connections are only reused between calls with equal settings.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct TlsOpts {
+    pub sni: Option<String>,
+    pub min_version: Option<crate::TlsVersion>,
+    pub max_version: Option<crate::TlsVersion>,
+    pub ciphers: Vec<String>,
+}
+
+/// WebSocket settings. Passed to WebSocket once call is started.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct WsOpts {
+    /// Sec-WebSocket-Key sent to server.
+    pub key: String,
+    /// Offered subprotocols.
+    pub protocols: Vec<String>,
+    pub deflate: Option<crate::WsDeflate>,
+    /// Max size of a received message. If 0 max_response is used.
+    pub max_message: usize,
+    pub auto_pong: bool,
+    pub ping_interval: Option<Duration>,
+    pub ping_timeout: Duration,
+    /// Max queued bytes for try_send_text/try_send_bin. 0 is unlimited.
+    pub max_send_queue: usize,
+    pub send_low_water: usize,
+}
+
 pub struct CallParam<'a> {
     pub poll: &'a Registry,
     pub dns: &'a mut DnsCache,
@@ -439,20 +703,41 @@ pub(crate) struct CallBuilderImpl {
     pub max_chunk: usize,
     pub dns_timeout: u64,
     pub ws: bool,
+    pub ws_opts: WsOpts,
+    // Started by CallBuilder::event_source.
+    pub event_source: bool,
     pub auth: AuthenticateInfo,
     pub digest: bool,
     pub max_redirects: u8,
     pub gzip: bool,
     pub insecure: bool,
+    pub tls_opts: TlsOpts,
     pub reused: bool,
     pub method: Method,
     pub body: Vec<u8>,
+    /// Streamed body, used if body is empty.
+    pub body_parts: Vec<BodyPart>,
+    pub compress: Option<crate::ContentEncoding>,
+    pub expect_continue: bool,
+    /// How long to wait for 100 Continue before sending body anyway.
+    pub continue_dur: Duration,
+    /// Invalid header was set with header(), call fails to start.
+    pub hdr_invalid: bool,
+    /// Body was already compressed by an earlier call (redirect or retry).
+    pub body_compressed: bool,
     pub tls: bool,
     pub port: u16,
     pub content_len: usize,
     pub ua_set: bool,
     pub con_set: bool,
     pub host_set: bool,
+    pub ae_set: bool,
+    // Settings set on call, HttpcCfg defaults do not replace them.
+    pub dur_set: bool,
+    pub max_redirects_set: bool,
+    pub gzip_set: bool,
+    pub max_response_set: bool,
+    pub max_chunk_set: bool,
     pub content_len_set: bool,
     pub transfer_encoding: TransferEncoding,
     pub bytes: Box<CallBytes>,
@@ -473,6 +758,7 @@ impl CallBuilderImpl {
             auth: AuthenticateInfo::empty(),
             port: 80,
             dur: Duration::from_millis(30000),
+            continue_dur: Duration::from_millis(1000),
             evids: [usize::max_value(), usize::max_value()],
             ..Default::default()
         }
@@ -487,14 +773,84 @@ impl CallBuilderImpl {
     pub fn url_hash(&self) -> u64 {
         self.bytes.url_hash(self.port)
     }
+    pub fn form(&mut self, kvl: &[(&str, &str)]) -> &mut Self {
+        fn encode(out: &mut Vec<u8>, s: &str) {
+            for chunk in utf8_percent_encode(s, FORM_ENCODE_SET) {
+                out.extend(chunk.bytes().map(|c| if c == b' ' { b'+' } else { c }));
+            }
+        }
+        self.set_header("Content-Type", "application/x-www-form-urlencoded");
+        self.body.clear();
+        self.body_parts.clear();
+        for (i, &(k, v)) in kvl.iter().enumerate() {
+            if i > 0 {
+                self.body.push(b'&');
+            }
+            encode(&mut self.body, k);
+            self.body.push(b'=');
+            encode(&mut self.body, v);
+        }
+        self
+    }
+    pub fn multipart(&mut self, m: crate::Multipart) -> &mut Self {
+        self.set_header("Content-Type", &m.content_type());
+        self.body.clear();
+        self.body_parts = m.finish();
+        self
+    }
+    pub fn expect_continue(&mut self, v: bool) -> &mut Self {
+        self.expect_continue = v;
+        self
+    }
+    pub fn expect_continue_ms(&mut self, v: u64) -> &mut Self {
+        self.continue_dur = Duration::from_millis(v);
+        self
+    }
+    pub fn compress_body(&mut self, enc: crate::ContentEncoding) -> &mut Self {
+        self.compress = Some(enc);
+        self
+    }
+    pub fn body_source(&mut self, src: crate::BodySource) -> &mut Self {
+        self.body.clear();
+        self.body_parts = vec![src.finish()];
+        self
+    }
     pub fn websocket(&mut self) -> &mut Self {
         self.ws = true;
+        let key: [u8; 16] = ::rand::random();
+        self.ws_opts.key = data_encoding::BASE64.encode(&key);
+        self
+    }
+    pub fn ws_deflate(&mut self, v: crate::WsDeflate) -> &mut Self {
+        self.ws_opts.deflate = Some(v);
+        self
+    }
+    pub fn ws_protocols(&mut self, v: &[&str]) -> &mut Self {
+        self.ws_opts.protocols = v.iter().map(|p| p.to_string()).collect();
+        self
+    }
+    pub fn ws_max_message(&mut self, v: usize) -> &mut Self {
+        self.ws_opts.max_message = v;
         self
     }
-    pub fn method(&mut self, m: &str) -> &mut Self {
-        self.method = Method::from_str(m);
+    pub fn ws_auto_pong(&mut self, v: bool) -> &mut Self {
+        self.ws_opts.auto_pong = v;
         self
     }
+    pub fn ws_keepalive(&mut self, interval: Duration, timeout: Duration) -> &mut Self {
+        self.ws_opts.ping_interval = Some(interval);
+        self.ws_opts.ping_timeout = timeout;
+        self
+    }
+    pub fn ws_send_queue(&mut self, max: usize, low_water: usize) -> &mut Self {
+        self.ws_opts.max_send_queue = max;
+        self.ws_opts.send_low_water = low_water.min(max);
+        self
+    }
+    pub fn method(&mut self, m: &str) -> crate::Result<&mut Self> {
+        self.method = Method::from_str(m)?;
+        Ok(self)
+    }
     pub fn is_fixed(&self) -> bool {
         !(self.evids[0] == usize::max_value() && self.evids[1] == usize::max_value())
     }
@@ -503,23 +859,28 @@ impl CallBuilderImpl {
     //     I: Deref<Target = str>,
     {
         let url = Url::parse(url.deref())?;
+        self.set_url(&url, false)
+    }
+    // Set everything from url. Fragment is ignored.
+    // If keep_auth is set, username and password are not replaced.
+    fn set_url(&mut self, url: &Url, keep_auth: bool) -> crate::Result<&mut Self> {
         if !url.has_host() {
             return Err(crate::Error::NoHost);
         }
         let host = url.host_str().unwrap();
         self.bytes.host.truncate(0);
         self.bytes.host.extend_from_slice(host.as_bytes());
-        if let Some(port) = url.port() {
-            self.port = port;
-        }
-        self.bytes.us.truncate(0);
-        self.bytes.us.extend_from_slice(url.username().as_bytes());
-        self.bytes.pw.truncate(0);
-        if let Some(pw) = url.password() {
-            self.bytes.pw.extend_from_slice(pw.as_bytes());
+        if !keep_auth {
+            self.bytes.us.truncate(0);
+            self.bytes.us.extend_from_slice(url.username().as_bytes());
+            self.bytes.pw.truncate(0);
+            if let Some(pw) = url.password() {
+                self.bytes.pw.extend_from_slice(pw.as_bytes());
+            }
         }
-        if url.scheme().eq_ignore_ascii_case("https") || url.scheme().eq_ignore_ascii_case("wss") {
-            self.https();
+        self.set_https(url.scheme() == "https" || url.scheme() == "wss");
+        if let Some(port) = url.port_or_known_default() {
+            self.port = port;
         }
         self.bytes.path.truncate(0);
         self.bytes.path.extend_from_slice(url.path().as_bytes());
@@ -530,6 +891,30 @@ impl CallBuilderImpl {
         }
         Ok(self)
     }
+    // URL of call without username and password.
+    pub fn to_url(&self) -> crate::Result<Url> {
+        if self.bytes.host.is_empty() {
+            return Err(crate::Error::NoHost);
+        }
+        let mut s = String::from(if self.tls { "https://" } else { "http://" });
+        s.push_str(&String::from_utf8_lossy(&self.bytes.host));
+        s.push(':');
+        s.push_str(itoa::Buffer::new().format(self.port));
+        if !self.bytes.path.starts_with(b"/") {
+            s.push('/');
+        }
+        s.push_str(&String::from_utf8_lossy(&self.bytes.path));
+        s.push_str(&String::from_utf8_lossy(&self.bytes.query));
+        Ok(Url::parse(&s)?)
+    }
+    // Resolve reference against current URL (RFC 3986).
+    // Username and password are kept if URL stays on the same origin.
+    pub fn join(&mut self, reference: &str) -> crate::Result<&mut Self> {
+        let base = self.to_url()?;
+        let url = base.join(reference)?;
+        let keep_auth = url.origin() == base.origin() && url.username().is_empty();
+        self.set_url(&url, keep_auth)
+    }
     pub fn query(&mut self, k: &str, v: &str) -> &mut Self
 // where
     //     I: Deref<Target = str>,
@@ -608,7 +993,32 @@ impl CallBuilderImpl {
         }
         self
     }
+    pub fn try_header(&mut self, key: &str, value: &str) -> crate::Result<&mut Self> {
+        if !is_token(key) || !is_field_value(value) {
+            return Err(crate::Error::InvalidHeader);
+        }
+        Ok(self.header_int(key, value.trim_matches(|c| c == ' ' || c == '\t')))
+    }
     pub fn header(&mut self, key: &str, value: &str) -> &mut Self {
+        if self.try_header(key, value).is_err() {
+            self.hdr_invalid = true;
+        }
+        self
+    }
+    // Check values that are written to request as is.
+    pub fn validate(&self) -> crate::Result<()> {
+        if self.hdr_invalid {
+            return Err(crate::Error::InvalidHeader);
+        }
+        if !is_host(&self.bytes.host) {
+            return Err(crate::Error::InvalidHost);
+        }
+        if !is_path(&self.bytes.path) {
+            return Err(crate::Error::InvalidPath);
+        }
+        Ok(())
+    }
+    fn header_int(&mut self, key: &str, value: &str) -> &mut Self {
         if key.eq_ignore_ascii_case("content-length") {
             if let Ok(bsz) = usize::from_str(value) {
                 self.content_len = bsz;
@@ -620,12 +1030,8 @@ impl CallBuilderImpl {
                 self.transfer_encoding = TransferEncoding::Chunked;
                 self.content_len = usize::max_value();
             }
-        } else if key.eq_ignore_ascii_case("user-agent") {
-            self.ua_set = true;
-        } else if key.eq_ignore_ascii_case("connection") {
-            self.con_set = true;
-        } else if key.eq_ignore_ascii_case("host") {
-            self.host_set = true;
+        } else {
+            self.auto_header(key, true);
         }
         self.bytes.headers.extend_from_slice(key.as_bytes());
         self.bytes.headers.extend_from_slice(b": ");
@@ -633,12 +1039,122 @@ impl CallBuilderImpl {
         self.bytes.headers.extend_from_slice(b"\r\n");
         self
     }
+    // Set if automatic header key is replaced by a user header or suppressed.
+    // Returns false if key is not an automatic header.
+    fn auto_header(&mut self, key: &str, set: bool) -> bool {
+        if key.eq_ignore_ascii_case("user-agent") {
+            self.ua_set = set;
+        } else if key.eq_ignore_ascii_case("connection") {
+            self.con_set = set;
+        } else if key.eq_ignore_ascii_case("host") {
+            self.host_set = set;
+        } else if key.eq_ignore_ascii_case("accept-encoding") {
+            self.ae_set = set;
+        } else {
+            return false;
+        }
+        true
+    }
+    pub fn set_header(&mut self, key: &str, value: &str) -> &mut Self {
+        self.remove_header(key);
+        self.header(key, value)
+    }
+    pub fn remove_header(&mut self, key: &str) -> &mut Self {
+        if key.eq_ignore_ascii_case("content-length") {
+            self.content_len = 0;
+            self.content_len_set = false;
+        } else if key.eq_ignore_ascii_case("transfer-encoding") {
+            self.transfer_encoding = TransferEncoding::Identity;
+        } else {
+            self.auto_header(key, false);
+        }
+        // Compact remaining lines in place.
+        let hdrs = &mut self.bytes.headers;
+        let (mut pos, mut out) = (0, 0);
+        while let Some(len) = header_line(&hdrs[pos..]) {
+            if !header_is(&hdrs[pos..pos + len], key) {
+                hdrs.copy_within(pos..pos + len, out);
+                out += len;
+            }
+            pos += len;
+        }
+        hdrs.truncate(out);
+        self
+    }
+    pub fn suppress_header(&mut self, key: &str) -> &mut Self {
+        self.remove_header(key);
+        self.auto_header(key, true);
+        self
+    }
+    // Header set on call or automatic header suppressed.
+    fn header_set(&self, key: &str) -> bool {
+        if key.eq_ignore_ascii_case("user-agent") {
+            self.ua_set
+        } else if key.eq_ignore_ascii_case("connection") {
+            self.con_set
+        } else if key.eq_ignore_ascii_case("host") {
+            self.host_set
+        } else if key.eq_ignore_ascii_case("accept-encoding") {
+            self.ae_set
+        } else if key.eq_ignore_ascii_case("content-length") {
+            self.content_len_set
+        } else if key.eq_ignore_ascii_case("authorization") && !self.bytes.us.is_empty() {
+            // Basic or digest Authorization is added from credentials.
+            true
+        } else {
+            self.get_header(key).is_some()
+        }
+    }
+    // Merge HttpcCfg defaults into settings not set on call.
+    // Defaults are marked as set so they are applied only once per call.
+    pub fn apply_defaults(&mut self, cfg: &crate::HttpcCfg) -> crate::Result<()> {
+        for (k, v) in cfg.headers.iter() {
+            if !is_token(k) || !is_field_value(v) {
+                return Err(crate::Error::InvalidDefaultHeader(k.clone()));
+            }
+        }
+        for (k, v) in cfg.headers.iter() {
+            if !self.header_set(k) {
+                self.header(k, v);
+            }
+        }
+        if let (false, Some(v)) = (self.dur_set, cfg.timeout_ms) {
+            self.timeout_ms(v);
+        }
+        if let (false, Some(v)) = (self.max_redirects_set, cfg.max_redirects) {
+            self.max_redirects(v);
+        }
+        if let (false, Some(v)) = (self.gzip_set, cfg.gzip) {
+            self.gzip(v);
+        }
+        if let (false, Some(v)) = (self.max_response_set, cfg.max_response) {
+            self.max_response(v);
+        }
+        if let (false, Some(v)) = (self.max_chunk_set, cfg.chunked_max_chunk) {
+            self.chunked_max_chunk(v);
+        }
+        Ok(())
+    }
+    // First value of header key. Content-Length is not stored as a header.
+    pub fn get_header(&self, key: &str) -> Option<&str> {
+        let mut b = &self.bytes.headers[..];
+        while let Some(len) = header_line(b) {
+            if header_is(&b[..len], key) {
+                let v = &b[key.len() + 1..len - 2];
+                // Only valid str was ever added.
+                return ::std::str::from_utf8(v).ok().map(|v| v.trim_start());
+            }
+            b = &b[len..];
+        }
+        None
+    }
     pub fn dns_retry_ms(&mut self, n: u64) -> &mut Self {
         self.dns_timeout = n;
         self
     }
     pub fn max_response(&mut self, m: usize) -> &mut Self {
         self.max_response = m;
+        self.max_response_set = true;
         self
     }
     pub fn digest_auth(&mut self, b: bool) -> &mut Self {
@@ -653,24 +1169,44 @@ impl CallBuilderImpl {
     }
     pub fn gzip(&mut self, b: bool) -> &mut Self {
         self.gzip = b;
+        self.gzip_set = true;
         self
     }
     pub fn chunked_max_chunk(&mut self, v: usize) -> &mut Self {
         self.max_chunk = v;
+        self.max_chunk_set = true;
         self
     }
     pub fn timeout_ms(&mut self, v: u64) -> &mut Self {
         self.dur = Duration::from_millis(v);
+        self.dur_set = true;
         self
     }
     pub fn max_redirects(&mut self, v: u8) -> &mut Self {
         self.max_redirects = v;
+        self.max_redirects_set = true;
         self
     }
     pub fn insecure(&mut self) -> &mut Self {
         self.insecure = true;
         self
     }
+    pub fn tls_sni(&mut self, name: &str) -> &mut Self {
+        self.tls_opts.sni = Some(name.to_string());
+        self
+    }
+    pub fn tls_min_version(&mut self, v: crate::TlsVersion) -> &mut Self {
+        self.tls_opts.min_version = Some(v);
+        self
+    }
+    pub fn tls_max_version(&mut self, v: crate::TlsVersion) -> &mut Self {
+        self.tls_opts.max_version = Some(v);
+        self
+    }
+    pub fn tls_ciphers(&mut self, ciphers: &[&str]) -> &mut Self {
+        self.tls_opts.ciphers = ciphers.iter().map(|c| c.to_string()).collect();
+        self
+    }
     pub fn get_url(&mut self) -> String {
         let mut s = Vec::new();
         if self.tls {
@@ -740,3 +1276,158 @@ pub fn test_auth() {
     assert_eq!(da.alg, DigestAlg::MD5);
     assert_eq!(da.stale, false);
 }
+
+#[test]
+pub fn test_header_validation() {
+    let mut b = CallBuilderImpl::new();
+    b.try_header("X-A", " b\tc ").unwrap();
+    b.try_header("X-Name", "Jürgen").unwrap();
+    assert_eq!(
+        &b.bytes.headers[..],
+        "X-A: b\tc\r\nX-Name: Jürgen\r\n".as_bytes()
+    );
+    for &(k, v) in &[
+        ("X-A", "b\r\nX-Evil: 1"),
+        ("X-A", "b\n"),
+        ("X-A", "b\0"),
+        ("Bad Name", "v"),
+        ("X-A:", "v"),
+        ("", "v"),
+    ] {
+        assert!(matches!(
+            b.try_header(k, v),
+            Err(crate::Error::InvalidHeader)
+        ));
+    }
+    assert!(b.validate().is_ok());
+    let len = b.bytes.headers.len();
+    b.header("X-B", "a\r\n\r\nGET / HTTP/1.1");
+    assert_eq!(b.bytes.headers.len(), len);
+    assert!(matches!(b.validate(), Err(crate::Error::InvalidHeader)));
+
+    let mut b = CallBuilderImpl::new();
+    b.host("[::1]").path("/a/b?c=1");
+    assert!(b.validate().is_ok());
+    b.path("/a b");
+    assert!(matches!(b.validate(), Err(crate::Error::InvalidPath)));
+    let mut b = CallBuilderImpl::new();
+    b.host("example.com\r\nX-Evil: 1");
+    assert!(matches!(b.validate(), Err(crate::Error::InvalidHost)));
+
+    let r = crate::CallBuilder::get()
+        .url("http://127.0.0.1:1/")
+        .unwrap()
+        .header("X-A", "\r\n")
+        .exec();
+    assert!(matches!(r, Err(crate::Error::InvalidHeader)));
+}
+
+#[test]
+pub fn test_header_map() {
+    let mut b = CallBuilderImpl::new();
+    b.header("X-A", "1").header("x-b", "2").header("x-a", "3");
+    assert_eq!(b.get_header("x-a"), Some("1"));
+    b.set_header("X-a", "4");
+    assert_eq!(&b.bytes.headers[..], b"x-b: 2\r\nX-a: 4\r\n");
+    b.remove_header("X-B").remove_header("X-C");
+    assert_eq!(&b.bytes.headers[..], b"X-a: 4\r\n");
+    assert_eq!(b.get_header("x-b"), None);
+    // Prefix of another header name does not match.
+    assert_eq!(b.get_header("x"), None);
+
+    b.set_header("User-Agent", "test");
+    assert!(b.ua_set);
+    b.remove_header("user-agent");
+    assert!(!b.ua_set);
+    b.suppress_header("Accept-Encoding");
+    assert!(b.ae_set);
+    assert_eq!(b.get_header("accept-encoding"), None);
+
+    b.header("Content-Length", "10");
+    assert!(b.content_len_set);
+    b.remove_header("content-length");
+    assert!(!b.content_len_set);
+}
+
+#[test]
+pub fn test_call_defaults() {
+    let mut cfg = crate::HttpcCfg::new();
+    cfg.headers = vec![
+        ("X-Trace".to_string(), "cfg".to_string()),
+        ("Authorization".to_string(), "Bearer cfg".to_string()),
+        ("User-Agent".to_string(), "svc/1".to_string()),
+    ];
+    cfg.timeout_ms = Some(500);
+    cfg.max_redirects = Some(1);
+    cfg.gzip = Some(false);
+    cfg.max_response = Some(1000);
+
+    let mut b = CallBuilderImpl::new();
+    b.header("x-trace", "call")
+        .timeout_ms(2000)
+        .max_response(10);
+    b.apply_defaults(&cfg).unwrap();
+    assert_eq!(b.get_header("X-Trace"), Some("call"));
+    assert_eq!(b.get_header("Authorization"), Some("Bearer cfg"));
+    assert_eq!(b.get_header("User-Agent"), Some("svc/1"));
+    assert_eq!(b.dur, Duration::from_millis(2000));
+    assert_eq!(b.max_response, 10);
+    assert_eq!(b.max_redirects, 1);
+    assert!(!b.gzip);
+    assert_eq!(b.max_chunk, 128 * 1024);
+
+    // Applied once, redirect keeps decremented count.
+    b.max_redirects -= 1;
+    let len = b.bytes.headers.len();
+    b.apply_defaults(&cfg).unwrap();
+    assert_eq!(b.max_redirects, 0);
+    assert_eq!(b.bytes.headers.len(), len);
+
+    let mut b = CallBuilderImpl::new();
+    b.suppress_header("User-Agent");
+    b.apply_defaults(&cfg).unwrap();
+    assert_eq!(b.get_header("User-Agent"), None);
+    // Authorization comes from credentials.
+    let mut b = CallBuilderImpl::new();
+    b.auth("us", "pw");
+    b.apply_defaults(&cfg).unwrap();
+    assert_eq!(b.get_header("Authorization"), None);
+    assert_eq!(b.get_header("X-Trace"), Some("cfg"));
+
+    cfg.headers
+        .push(("X-Bad".to_string(), "a\r\nb".to_string()));
+    let mut b = CallBuilderImpl::new();
+    b.header("X-Bad", "call");
+    match b.apply_defaults(&cfg) {
+        Err(crate::Error::InvalidDefaultHeader(k)) => assert_eq!(k, "X-Bad"),
+        r => panic!("{:?}", r),
+    }
+}
+
+#[test]
+pub fn test_url_join() {
+    let mut b = CallBuilderImpl::new();
+    b.url("http://us:pw@h/api/v1/x?q").unwrap();
+    assert_eq!(b.to_url().unwrap().as_str(), "http://h/api/v1/x?q");
+
+    b.join("../v2/y?z=1").unwrap();
+    assert_eq!(b.to_url().unwrap().as_str(), "http://h/api/v2/y?z=1");
+    assert_eq!(&b.bytes.us[..], b"us");
+    b.join("?q2").unwrap();
+    assert_eq!(b.to_url().unwrap().as_str(), "http://h/api/v2/y?q2");
+    b.join("/abs").unwrap();
+    assert_eq!(&b.bytes.path[..], b"/abs");
+    assert!(b.bytes.query.is_empty());
+    assert_eq!(&b.bytes.pw[..], b"pw");
+
+    // Other origin drops auth, scheme relative reference keeps scheme.
+    b.join("//other:8080/p").unwrap();
+    assert_eq!(b.to_url().unwrap().as_str(), "http://other:8080/p");
+    assert!(b.bytes.us.is_empty() && b.bytes.pw.is_empty());
+
+    b.join("https://secure/").unwrap();
+    assert!(b.tls);
+    assert_eq!(b.port, 443);
+    assert!(b.join("http://[bad").is_err());
+    assert_eq!(b.to_url().unwrap().as_str(), "https://secure/");
+}
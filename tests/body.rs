@@ -0,0 +1,357 @@
+// Request bodies against an in-process server.
+mod common;
+
+use common::{accept, read_head, serve, server};
+use mio_httpc::{BodySource, CallBuilder, ContentEncoding, Error, Httpc, Multipart, SendState};
+use std::io::{Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn post(port: u16, src: BodySource) {
+    let (resp, _) = CallBuilder::post(Vec::new())
+        .timeout_ms(3000)
+        .url(&format!("http://127.0.0.1:{}/", port))
+        .unwrap()
+        .body_source(src)
+        .exec()
+        .unwrap();
+    assert_eq!(resp.status, 200);
+}
+
+#[test]
+fn multipart_upload() {
+    let content: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    let path = std::env::temp_dir().join(format!("mio_httpc_mp_{}.bin", std::process::id()));
+    std::fs::write(&path, &content).unwrap();
+
+    let mut mp = Multipart::new();
+    mp.text("title", "a \"b\"")
+        .bytes("meta", None, "application/json; charset=\"utf-8\"", b"{}")
+        .unwrap();
+    mp.file(
+        "upload",
+        &path,
+        Some("data.bin"),
+        "application/octet-stream",
+    )
+    .unwrap();
+    let boundary = mp.boundary().to_string();
+    let len = mp.content_len();
+
+    let (port, server) = serve();
+    let (resp, _) = CallBuilder::post(Vec::new())
+        .timeout_ms(3000)
+        .url(&format!("http://127.0.0.1:{}/", port))
+        .unwrap()
+        .multipart(mp)
+        .exec()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(resp.status, 200);
+
+    let (head, body) = server.join().unwrap();
+    assert!(head.contains(&format!(
+        "Content-Type: multipart/form-data; boundary={}\r\n",
+        boundary
+    )));
+    assert!(head.contains(&format!("Content-Length: {}\r\n", len)));
+    let mut expect = format!(
+        "--{b}\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\na \"b\"\r\n\
+         --{b}\r\nContent-Disposition: form-data; name=\"meta\"\r\n\
+         Content-Type: application/json; charset=\"utf-8\"\r\n\r\n{{}}\r\n\
+         --{b}\r\nContent-Disposition: form-data; name=\"upload\"; filename=\"data.bin\"\r\n\
+         Content-Type: application/octet-stream\r\n\r\n",
+        b = boundary
+    )
+    .into_bytes();
+    expect.extend_from_slice(&content);
+    expect.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    assert_eq!(body.len(), expect.len());
+    assert!(body == expect);
+}
+
+#[test]
+fn multipart_invalid_content_type() {
+    let mut mp = Multipart::new();
+    let r = mp.bytes("a", None, "text/plain\r\nX-Injected: 1", b"x");
+    assert!(matches!(r, Err(Error::InvalidHeader)));
+    let r = mp.file("b", "Cargo.toml", None, "");
+    assert!(matches!(r, Err(Error::InvalidHeader)));
+    // Rejected parts add nothing to body.
+    assert_eq!(mp.content_len(), Multipart::new().content_len());
+}
+
+#[test]
+fn form_body() {
+    let (port, server) = serve();
+    let (resp, _) = CallBuilder::post(Vec::new())
+        .timeout_ms(3000)
+        .url(&format!("http://127.0.0.1:{}/token", port))
+        .unwrap()
+        .form(&[
+            ("grant_type", "client_credentials"),
+            ("scope", "read write"),
+            ("q", "a+b&c=d/é*~"),
+        ])
+        .exec()
+        .unwrap();
+    assert_eq!(resp.status, 200);
+    let (head, body) = server.join().unwrap();
+    assert!(head.contains("Content-Type: application/x-www-form-urlencoded\r\n"));
+    assert_eq!(
+        String::from_utf8(body).unwrap(),
+        "grant_type=client_credentials&scope=read+write&q=a%2Bb%26c%3Dd%2F%C3%A9*%7E"
+    );
+}
+
+#[test]
+fn body_source_file() {
+    let content: Vec<u8> = (0..100_000u32).map(|i| (i % 253) as u8).collect();
+    let path = std::env::temp_dir().join(format!("mio_httpc_src_{}.bin", std::process::id()));
+    std::fs::write(&path, &content).unwrap();
+    let src = BodySource::file(&path).unwrap();
+    assert_eq!(src.content_len(), Some(content.len() as u64));
+
+    let (port, server) = serve();
+    post(port, src);
+    std::fs::remove_file(&path).unwrap();
+    let (head, body) = server.join().unwrap();
+    assert!(head.contains("Content-Length: 100000\r\n"), "{}", head);
+    assert!(!head.contains("Transfer-Encoding"));
+    assert!(body == content);
+}
+
+#[test]
+fn body_source_chunked() {
+    let content: Vec<u8> = (0..100_000u32).map(|i| (i % 241) as u8).collect();
+    let (port, server) = serve();
+    post(
+        port,
+        BodySource::reader(std::io::Cursor::new(content.clone()), None),
+    );
+    let (head, body) = server.join().unwrap();
+    assert!(!head.contains("Content-Length"), "{}", head);
+    assert!(body == content);
+
+    // Callback with known length.
+    let mut n = 0;
+    let src = BodySource::callback(
+        move |buf: &mut [u8]| {
+            let sz = buf.len().min(3000 - n).min(1000);
+            buf[..sz].iter_mut().for_each(|c| *c = b'x');
+            n += sz;
+            Ok(sz)
+        },
+        Some(3000),
+    );
+    let (port, server) = serve();
+    post(port, src);
+    let (head, body) = server.join().unwrap();
+    assert!(head.contains("Content-Length: 3000\r\n"), "{}", head);
+    assert_eq!(body, vec![b'x'; 3000]);
+}
+
+#[test]
+fn compress_body() {
+    let json = format!("[{}]", vec!["{\"level\":\"info\",\"n\":1}"; 2000].join(","));
+    let (port, server) = serve();
+    let (resp, _) = CallBuilder::post(json.clone().into_bytes())
+        .timeout_ms(3000)
+        .url(&format!("http://127.0.0.1:{}/", port))
+        .unwrap()
+        .compress_body(ContentEncoding::Gzip)
+        .exec()
+        .unwrap();
+    assert_eq!(resp.status, 200);
+    let (head, body) = server.join().unwrap();
+    assert!(head.contains("Content-Encoding: gzip\r\n"), "{}", head);
+    assert!(head.contains(&format!("Content-Length: {}\r\n", body.len())));
+    assert!(body.len() < json.len() / 10);
+    let mut out = String::new();
+    flate2::read::GzDecoder::new(&body[..])
+        .read_to_string(&mut out)
+        .unwrap();
+    assert_eq!(out, json);
+
+    // Content-Length set by caller is of uncompressed body and is replaced.
+    let (port, server) = serve();
+    let (resp, _) = CallBuilder::post(json.clone().into_bytes())
+        .timeout_ms(3000)
+        .url(&format!("http://127.0.0.1:{}/", port))
+        .unwrap()
+        .header("Content-Length", &json.len().to_string())
+        .compress_body(ContentEncoding::Gzip)
+        .exec()
+        .unwrap();
+    assert_eq!(resp.status, 200);
+    let (head, body) = server.join().unwrap();
+    assert_eq!(head.matches("Content-Length").count(), 1, "{}", head);
+    assert!(head.contains(&format!("Content-Length: {}\r\n", body.len())));
+    assert!(body.len() < json.len() / 10);
+
+    // Streamed body is sent chunked.
+    let content: Vec<u8> = (0..200_000u32).map(|i| (i % 7) as u8).collect();
+    let (port, server) = serve();
+    let (resp, _) = CallBuilder::post(Vec::new())
+        .timeout_ms(3000)
+        .url(&format!("http://127.0.0.1:{}/", port))
+        .unwrap()
+        .header("Content-Length", &content.len().to_string())
+        .body_source(BodySource::reader(
+            std::io::Cursor::new(content.clone()),
+            Some(content.len() as u64),
+        ))
+        .compress_body(ContentEncoding::Deflate)
+        .exec()
+        .unwrap();
+    assert_eq!(resp.status, 200);
+    let (head, body) = server.join().unwrap();
+    assert!(head.contains("Content-Encoding: deflate\r\n"), "{}", head);
+    assert!(!head.contains("Content-Length"), "{}", head);
+    let mut out = Vec::new();
+    flate2::read::DeflateDecoder::new(&body[..])
+        .read_to_end(&mut out)
+        .unwrap();
+    assert!(out == content);
+}
+
+// Serve one request with Expect: 100-continue. If reply is set it is sent instead of
+// 100 Continue, otherwise server waits for body. Returns request head and body.
+fn serve_expect(
+    reply: Option<&'static str>,
+    continue_first: bool,
+) -> (u16, thread::JoinHandle<(String, Vec<u8>)>) {
+    server(move |listener| {
+        let mut s = accept(&listener);
+        let head = read_head(&mut s);
+        let mut body = Vec::new();
+        if let Some(reply) = reply {
+            s.write_all(reply.as_bytes()).unwrap();
+            // Client must not send body, it closes connection instead.
+            s.set_read_timeout(Some(Duration::from_millis(300)))
+                .unwrap();
+            let mut b = [0u8; 1024];
+            if let Ok(n) = s.read(&mut b) {
+                body.extend_from_slice(&b[..n]);
+            }
+            return (head, body);
+        }
+        if continue_first {
+            s.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").unwrap();
+        }
+        body.resize(5, 0);
+        s.read_exact(&mut body).unwrap();
+        s.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+        (head, body)
+    })
+}
+
+fn post_expect_ms(port: u16, ms: u64) -> (Result<(u16, Vec<u8>), Error>, Duration) {
+    let start = Instant::now();
+    let r = CallBuilder::post(b"hello".to_vec())
+        .timeout_ms(3000)
+        .url(&format!("http://127.0.0.1:{}/", port))
+        .unwrap()
+        .expect_continue(true)
+        .expect_continue_ms(ms)
+        .exec()
+        .map(|(resp, body)| (resp.status, body));
+    (r, start.elapsed())
+}
+
+fn post_expect(port: u16) -> (u16, Vec<u8>, Duration) {
+    let (r, dur) = post_expect_ms(port, 1000);
+    let (status, body) = r.unwrap();
+    (status, body, dur)
+}
+
+#[test]
+fn expect_continue() {
+    let (port, server) = serve_expect(None, true);
+    let (status, _, dur) = post_expect(port);
+    assert_eq!(status, 200);
+    assert!(dur < Duration::from_millis(900), "{:?}", dur);
+    let (head, body) = server.join().unwrap();
+    assert!(head.contains("Expect: 100-continue\r\n"), "{}", head);
+    assert_eq!(body, b"hello");
+
+    // Final status instead of 100 Continue, body is not sent.
+    let (port, server) = serve_expect(
+        Some("HTTP/1.1 413 Payload Too Large\r\nContent-Length: 3\r\n\r\nbig"),
+        false,
+    );
+    let (status, body, _) = post_expect(port);
+    assert_eq!(status, 413);
+    assert_eq!(body, b"big");
+    let (_, body) = server.join().unwrap();
+    assert!(body.is_empty());
+
+    // No reply, body is sent after a timeout.
+    let (port, server) = serve_expect(None, false);
+    let (status, _, dur) = post_expect(port);
+    assert_eq!(status, 200);
+    assert!(dur >= Duration::from_millis(900), "{:?}", dur);
+    assert_eq!(server.join().unwrap().1, b"hello");
+
+    // Shorter wait set on call.
+    let (port, server) = serve_expect(None, false);
+    let (r, dur) = post_expect_ms(port, 200);
+    assert_eq!(r.unwrap().0, 200);
+    assert!(dur < Duration::from_millis(900), "{:?}", dur);
+    assert_eq!(server.join().unwrap().1, b"hello");
+
+    // Response stalls half way, call fails without sending body.
+    let (port, server) = serve_expect(Some("HTTP/1.1 200 OK\r\nContent-"), false);
+    let (r, dur) = post_expect_ms(port, 200);
+    assert!(matches!(r, Err(Error::TimeOut)), "{:?}", r);
+    assert!(dur < Duration::from_millis(900), "{:?}", dur);
+    assert!(server.join().unwrap().1.is_empty());
+}
+
+#[test]
+fn expect_continue_chunked_stream() {
+    // Body framed by caller and streamed with call_send.
+    let data = b"5\r\nhello\r\n0\r\n\r\n";
+    let (port, server) = server(move |listener| {
+        let mut s = accept(&listener);
+        let head = read_head(&mut s);
+        if head.contains("Expect: 100-continue\r\n") {
+            s.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").unwrap();
+        }
+        let mut body = vec![0u8; data.len()];
+        s.read_exact(&mut body).unwrap();
+        (head, body)
+    });
+    let mut htp = Httpc::new(0, None);
+    let mut poll = mio::Poll::new().unwrap();
+    let mut events = mio::Events::with_capacity(4);
+    let start = Instant::now();
+    let mut call = CallBuilder::post(Vec::new())
+        .timeout_ms(3000)
+        .url(&format!("http://127.0.0.1:{}/", port))
+        .unwrap()
+        .header("Transfer-Encoding", "chunked")
+        .expect_continue(true)
+        .expect_continue_ms(2000)
+        .call(&mut htp, poll.registry())
+        .unwrap();
+    let mut pos = 0;
+    while pos < data.len() {
+        assert!(start.elapsed() < Duration::from_millis(900));
+        poll.poll(&mut events, Some(Duration::from_millis(100)))
+            .unwrap();
+        for ev in events.iter() {
+            htp.event(&ev);
+        }
+        match htp.call_send(poll.registry(), &mut call, Some(&data[pos..])) {
+            SendState::SentBody(n) => pos += n,
+            SendState::Wait | SendState::WaitContinue | SendState::WaitReqBody => {}
+            st => panic!("{:?}", st),
+        }
+    }
+    let (head, body) = server.join().unwrap();
+    assert!(head.contains("Expect: 100-continue\r\n"), "{}", head);
+    assert!(!head.contains("Content-Length"), "{}", head);
+    assert_eq!(&body[..], &data[..]);
+}
@@ -0,0 +1,116 @@
+// In-process test server shared by integration tests.
+#![allow(dead_code)]
+use mio_httpc::{CallBuilder, Error, Httpc, Response};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+// Run f on a thread with a listener on a free local port.
+pub fn server<T, F>(f: F) -> (u16, thread::JoinHandle<T>)
+where
+    F: FnOnce(TcpListener) -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    (port, thread::spawn(move || f(listener)))
+}
+
+// Accept a connection. Reads time out so a stuck client fails the test instead of hanging.
+pub fn accept(listener: &TcpListener) -> TcpStream {
+    let (s, _) = listener.accept().unwrap();
+    s.set_read_timeout(Some(Duration::from_secs(3))).unwrap();
+    s
+}
+
+pub fn read_line(s: &mut TcpStream) -> String {
+    let mut line = Vec::new();
+    let mut b = [0u8; 1];
+    while !line.ends_with(b"\r\n") {
+        s.read_exact(&mut b).unwrap();
+        line.push(b[0]);
+    }
+    String::from_utf8(line).unwrap()
+}
+
+// Request line and headers including the empty line.
+pub fn read_head(s: &mut TcpStream) -> String {
+    let mut head = String::new();
+    while !head.ends_with("\r\n\r\n") {
+        head.push_str(&read_line(s));
+    }
+    head
+}
+
+// Request body with Content-Length or chunked encoding, empty if neither is set.
+pub fn read_body(s: &mut TcpStream, head: &str) -> Vec<u8> {
+    let len = head
+        .lines()
+        .find(|l| l.to_ascii_lowercase().starts_with("content-length:"))
+        .map(|l| l[15..].trim().parse().unwrap());
+    let mut body = Vec::new();
+    if let Some(len) = len {
+        body.resize(len, 0);
+        s.read_exact(&mut body).unwrap();
+    } else if head.contains("Transfer-Encoding: chunked\r\n") {
+        loop {
+            let sz = usize::from_str_radix(read_line(s).trim_end(), 16).unwrap();
+            let start = body.len();
+            body.resize(start + sz, 0);
+            s.read_exact(&mut body[start..]).unwrap();
+            assert_eq!(read_line(s), "\r\n");
+            if sz == 0 {
+                break;
+            }
+        }
+    }
+    body
+}
+
+// Serve one request with an empty 200 response, return request head and body.
+pub fn serve() -> (u16, thread::JoinHandle<(String, Vec<u8>)>) {
+    server(|listener| {
+        let mut s = accept(&listener);
+        let head = read_head(&mut s);
+        let body = read_body(&mut s, &head);
+        s.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+        (head, body)
+    })
+}
+
+// Execute cb against serve() at /res?a=1, return request head and body.
+pub fn exec(mut cb: CallBuilder) -> (String, Vec<u8>) {
+    let (port, server) = serve();
+    let (resp, _) = cb
+        .timeout_ms(3000)
+        .url(&format!("http://127.0.0.1:{}/res?a=1", port))
+        .unwrap()
+        .exec()
+        .unwrap();
+    assert_eq!(resp.status, 200);
+    server.join().unwrap()
+}
+
+// Like CallBuilder::exec, but on htp so its config and keep-alive connections are used.
+pub fn exec_on(
+    htp: &mut Httpc,
+    poll: &mut mio::Poll,
+    cb: &mut CallBuilder,
+) -> mio_httpc::Result<(Response, Vec<u8>)> {
+    let mut events = mio::Events::with_capacity(4);
+    let mut call = cb.simple_call(htp, poll.registry())?;
+    loop {
+        poll.poll(&mut events, Some(Duration::from_millis(100)))?;
+        if htp.timeout().into_iter().any(|c| call.is_ref(c)) {
+            return Err(Error::TimeOut);
+        }
+        for ev in events.iter() {
+            htp.event(&ev);
+        }
+        if call.perform(htp, poll.registry())? {
+            return call.finish().ok_or(Error::Closed);
+        }
+    }
+}
@@ -0,0 +1,47 @@
+// Endpoint templates against an in-process server.
+mod common;
+
+use common::serve;
+use mio_httpc::Endpoint;
+
+#[test]
+fn endpoint_calls() {
+    let (port, server) = serve();
+    let mut api = Endpoint::new(&format!("http://127.0.0.1:{}/api/v1", port)).unwrap();
+    api.header("Authorization", "Bearer t").unwrap();
+    let (resp, _) = api
+        .post(b"{}".to_vec())
+        .path_segms(&["users", "a b"])
+        .query("f", "1")
+        .timeout_ms(3000)
+        .exec()
+        .unwrap();
+    assert_eq!(resp.status, 200);
+    let (head, body) = server.join().unwrap();
+    assert!(
+        head.starts_with("POST /api/v1/users/a%20b?f=1 HTTP/1.1\r\n"),
+        "{}",
+        head
+    );
+    assert!(head.contains("\r\nAuthorization: Bearer t\r\n"), "{}", head);
+    assert_eq!(body, b"{}");
+
+    // Template is unchanged by derived calls.
+    assert_eq!(api.url(), format!("http://127.0.0.1:{}/api/v1", port));
+
+    let (port, server) = serve();
+    let api = Endpoint::new(&format!("http://127.0.0.1:{}/api/v1/", port)).unwrap();
+    let (resp, _) = api
+        .join("GET", "../v2/items?x=1")
+        .unwrap()
+        .timeout_ms(3000)
+        .exec()
+        .unwrap();
+    assert_eq!(resp.status, 200);
+    let (head, _) = server.join().unwrap();
+    assert!(
+        head.starts_with("GET /api/v2/items?x=1 HTTP/1.1\r\n"),
+        "{}",
+        head
+    );
+}
@@ -0,0 +1,91 @@
+// Request headers and Httpc defaults against an in-process server.
+mod common;
+
+use common::{exec, exec_on, serve};
+use mio_httpc::{CallBuilder, Error, Httpc, HttpcCfg};
+
+#[test]
+fn auto_headers() {
+    let mut cb = CallBuilder::get();
+    cb.set_header("User-Agent", "agent/1")
+        .header("X-A", "1")
+        .set_header("X-A", "2")
+        .suppress_header("Accept-Encoding");
+    let (head, _) = exec(cb);
+    assert!(head.contains("\r\nUser-Agent: agent/1\r\n"), "{}", head);
+    assert_eq!(head.matches("User-Agent").count(), 1, "{}", head);
+    assert!(head.contains("\r\nX-A: 2\r\n"), "{}", head);
+    assert!(!head.contains("X-A: 1"), "{}", head);
+    assert!(!head.contains("Accept-Encoding"), "{}", head);
+    assert!(head.contains("\r\nHost: 127.0.0.1\r\n"), "{}", head);
+
+    let mut cb = CallBuilder::get();
+    cb.header("Connection", "close")
+        .remove_header("Connection")
+        .suppress_header("User-Agent");
+    let (head, _) = exec(cb);
+    assert!(head.contains("\r\nConnection: keep-alive\r\n"), "{}", head);
+    assert!(
+        head.contains("\r\nAccept-Encoding: gzip, deflate\r\n"),
+        "{}",
+        head
+    );
+    assert!(!head.contains("User-Agent"), "{}", head);
+}
+
+// Run GET on an Httpc with cfg, return request head. setup runs after url is set.
+fn exec_cfg(cfg: HttpcCfg, setup: fn(&mut CallBuilder)) -> Result<String, Error> {
+    let mut htp = Httpc::new(0, Some(cfg));
+    let mut poll = mio::Poll::new().unwrap();
+    let (port, server) = serve();
+    let mut cb = CallBuilder::get();
+    cb.timeout_ms(3000)
+        .url(&format!("http://127.0.0.1:{}/", port))
+        .unwrap();
+    setup(&mut cb);
+    let (resp, _) = exec_on(&mut htp, &mut poll, &mut cb)?;
+    assert_eq!(resp.status, 200);
+    Ok(server.join().unwrap().0)
+}
+
+fn cfg_headers(headers: &[(&str, &str)]) -> HttpcCfg {
+    let mut cfg = HttpcCfg::new();
+    cfg.headers = headers
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    cfg
+}
+
+#[test]
+fn httpc_defaults() {
+    let mut cfg = cfg_headers(&[("Authorization", "Bearer t"), ("X-Trace", "cfg")]);
+    cfg.gzip = Some(false);
+    let head = exec_cfg(cfg, |cb| {
+        cb.set_header("X-Trace", "call");
+    })
+    .unwrap();
+    assert!(head.contains("\r\nAuthorization: Bearer t\r\n"), "{}", head);
+    assert!(head.contains("\r\nX-Trace: call\r\n"), "{}", head);
+    assert!(!head.contains("X-Trace: cfg"), "{}", head);
+    assert!(!head.contains("Accept-Encoding"), "{}", head);
+
+    // Credentials on call replace default Authorization.
+    let cfg = cfg_headers(&[("Authorization", "Bearer t")]);
+    let head = exec_cfg(cfg, |cb| {
+        cb.auth("us", "pw");
+    })
+    .unwrap();
+    assert_eq!(head.matches("Authorization").count(), 1, "{}", head);
+    assert!(
+        head.contains("\r\nAuthorization: Basic dXM6cHc=\r\n"),
+        "{}",
+        head
+    );
+
+    let cfg = cfg_headers(&[("X-Bad\r\n", "1")]);
+    match exec_cfg(cfg, |_| {}) {
+        Err(Error::InvalidDefaultHeader(k)) => assert_eq!(k, "X-Bad\r\n"),
+        r => panic!("{:?}", r),
+    }
+}
@@ -0,0 +1,41 @@
+// Keep-alive connection reuse against an in-process server.
+mod common;
+
+use common::{accept, exec_on, read_head, server};
+use mio_httpc::{CallBuilder, Httpc, TlsVersion};
+use std::io::Write;
+use std::net::TcpStream;
+
+fn respond(s: &mut TcpStream) {
+    read_head(s);
+    s.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+        .unwrap();
+}
+
+#[test]
+fn keepalive_tls_opts() {
+    // Calls with different TLS settings each keep their own idle connection.
+    // If one replaced the other a third connection is opened and server read times out.
+    let (port, server) = server(|listener| {
+        let mut a = accept(&listener);
+        respond(&mut a);
+        let mut b = accept(&listener);
+        respond(&mut b);
+        respond(&mut a);
+        respond(&mut b);
+    });
+    let mut htp = Httpc::new(0, None);
+    let mut poll = mio::Poll::new().unwrap();
+    for &tls12 in [false, true, false, true].iter() {
+        let mut cb = CallBuilder::get();
+        cb.timeout_ms(3000)
+            .url(&format!("http://127.0.0.1:{}/", port))
+            .unwrap();
+        if tls12 {
+            cb.tls_min_version(TlsVersion::Tls12);
+        }
+        let (resp, _) = exec_on(&mut htp, &mut poll, &mut cb).unwrap();
+        assert_eq!(resp.status, 200);
+    }
+    server.join().unwrap();
+}
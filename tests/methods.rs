@@ -0,0 +1,46 @@
+// Request methods against an in-process server.
+mod common;
+
+use common::exec;
+use mio_httpc::{CallBuilder, Error};
+
+#[test]
+fn method_patch() {
+    let (head, body) = exec(CallBuilder::patch(b"{\"a\":1}".to_vec()));
+    assert!(head.starts_with("PATCH /res?a=1 HTTP/1.1\r\n"), "{}", head);
+    assert_eq!(body, b"{\"a\":1}");
+}
+
+#[test]
+fn method_from_str() {
+    let mut cb = CallBuilder::get();
+    cb.method("trace").unwrap();
+    let (head, _) = exec(cb);
+    assert!(head.starts_with("TRACE /res?a=1 HTTP/1.1\r\n"), "{}", head);
+
+    let mut cb = CallBuilder::get();
+    cb.method("PROPFIND").unwrap();
+    let (head, _) = exec(cb);
+    assert!(
+        head.starts_with("PROPFIND /res?a=1 HTTP/1.1\r\n"),
+        "{}",
+        head
+    );
+
+    for m in &["", "GET /", "PATCH\r\n", "P(T)"] {
+        assert!(matches!(
+            CallBuilder::get().method(m),
+            Err(Error::InvalidMethod)
+        ));
+    }
+}
+
+#[test]
+fn method_connect() {
+    let mut cb = CallBuilder::get();
+    cb.method("CONNECT").unwrap();
+    let (head, _) = exec(cb);
+    let line = head.lines().next().unwrap();
+    assert!(line.starts_with("CONNECT 127.0.0.1:"), "{}", head);
+    assert!(line.ends_with(" HTTP/1.1"), "{}", head);
+}
@@ -0,0 +1,159 @@
+// EventSource against an in-process server.
+mod common;
+
+use common::{accept, read_head, server};
+use mio_httpc::{CallBuilder, Error, Httpc, SseEvent};
+use std::io::Write;
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn chunk(s: &mut TcpStream, data: &str) {
+    write!(s, "{:x}\r\n{}\r\n", data.len(), data).unwrap();
+    s.flush().unwrap();
+    thread::sleep(Duration::from_millis(10));
+}
+
+// Serve responses to consecutive connections, return requests.
+fn serve(responses: Vec<fn(&mut TcpStream)>) -> (u16, thread::JoinHandle<Vec<String>>) {
+    server(move |listener| {
+        let mut reqs = Vec::new();
+        for resp in responses {
+            let mut s = accept(&listener);
+            reqs.push(read_head(&mut s));
+            resp(&mut s);
+        }
+        reqs
+    })
+}
+
+fn run(port: u16) -> (Vec<SseEvent>, Option<Error>, String) {
+    let mut poll = mio::Poll::new().unwrap();
+    let mut htp = Httpc::new(0, None);
+    let mut es = CallBuilder::get()
+        .url(&format!("http://127.0.0.1:{}/events", port))
+        .unwrap()
+        .event_source(&mut htp, poll.registry())
+        .unwrap();
+    let mut events = mio::Events::with_capacity(8);
+    let mut got = Vec::new();
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(3) {
+        poll.poll(&mut events, Some(Duration::from_millis(10)))
+            .unwrap();
+        for ev in events.iter() {
+            htp.event(&ev);
+        }
+        loop {
+            match es.recv_event(&mut htp, poll.registry()) {
+                Ok(Some(ev)) => got.push(ev),
+                Ok(None) => break,
+                Err(e) => {
+                    let id = es.last_event_id().to_string();
+                    es.finish(&mut htp);
+                    return (got, Some(e), id);
+                }
+            }
+        }
+    }
+    let id = es.last_event_id().to_string();
+    es.finish(&mut htp);
+    (got, None, id)
+}
+
+fn ev(event: &str, data: &str, id: &str) -> SseEvent {
+    SseEvent {
+        event: event.to_string(),
+        data: data.to_string(),
+        id: id.to_string(),
+    }
+}
+
+#[test]
+fn sse_reconnect() {
+    let (port, server) = serve(vec![
+        |s| {
+            write!(
+                s,
+                "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\
+                 Transfer-Encoding: chunked\r\nConnection: close\r\n\r\n"
+            )
+            .unwrap();
+            chunk(s, "\u{feff}retry: 50\n: hi\n\ndata: one\n");
+            chunk(s, "\ndata: tw");
+            chunk(s, "o\r\ndata: lines\r\nid: 5\r");
+            chunk(s, "\n\r\nevent: update\nda");
+            chunk(s, "ta: {}\n\ndata: lost");
+            s.write_all(b"0\r\n\r\n").unwrap();
+        },
+        |s| {
+            write!(
+                s,
+                "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\r\n\
+                 id\ndata: after\n\n"
+            )
+            .unwrap();
+        },
+        |s| {
+            write!(s, "HTTP/1.1 204 No Content\r\n\r\n").unwrap();
+        },
+    ]);
+    let (got, err, id) = run(port);
+    assert_eq!(
+        got,
+        vec![
+            ev("message", "one", ""),
+            ev("message", "two\nlines", "5"),
+            ev("update", "{}", "5"),
+            ev("message", "after", ""),
+        ]
+    );
+    assert!(matches!(err, Some(Error::Closed)), "{:?}", err);
+    assert_eq!(id, "");
+    let reqs = server.join().unwrap();
+    assert!(reqs[0].contains("Accept: text/event-stream\r\n"));
+    assert!(!reqs[0].contains("Last-Event-ID"));
+    assert!(reqs[1].contains("Last-Event-ID: 5\r\n"));
+    // id reset by empty id field
+    assert!(!reqs[2].contains("Last-Event-ID"));
+}
+
+#[test]
+fn sse_not_event_stream() {
+    let (port, server) = serve(vec![|s| {
+        write!(
+            s,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 2\r\n\r\nhi"
+        )
+        .unwrap();
+    }]);
+    let (got, err, _) = run(port);
+    assert!(got.is_empty());
+    match err {
+        Some(Error::EventSourceFail(resp)) => assert_eq!(resp.status, 200),
+        e => panic!("unexpected {:?}", e),
+    }
+    server.join().unwrap();
+}
+
+#[test]
+fn sse_plain_call_timeout() {
+    // Only EventSource extends timeout for an event stream.
+    let (port, server) = serve(vec![|s| {
+        write!(
+            s,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\r\ndata: x\n\n"
+        )
+        .unwrap();
+        thread::sleep(Duration::from_millis(1500));
+    }]);
+    let start = Instant::now();
+    let r = CallBuilder::get()
+        .timeout_ms(300)
+        .url(&format!("http://127.0.0.1:{}/events", port))
+        .unwrap()
+        .exec();
+    assert!(matches!(r, Err(Error::TimeOut)), "{:?}", r.map(|r| r.0));
+    assert!(start.elapsed() < Duration::from_millis(1200));
+    server.join().unwrap();
+}
@@ -0,0 +1,263 @@
+// TLS options against an in-process rustls server.
+#![cfg(feature = "rtls")]
+mod common;
+
+use common::{accept, exec_on, server};
+use mio_httpc::{CallBuilder, Error, Httpc, HttpcCfg, PinSet, RootCerts, TlsVersion};
+use rustls::version::{TLS12, TLS13};
+use rustls::{
+    CipherSuite, ProtocolVersion, ServerConfig, ServerConnection, SupportedProtocolVersion,
+};
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// Test CA and a certificate it issued for localhost and api.test.local.
+const CA: &[u8] = include_bytes!("tls/ca.pem");
+const CERT: &[u8] = include_bytes!("tls/server.pem");
+const KEY: &[u8] = include_bytes!("tls/server.key");
+// Unrelated CA.
+const OTHER_CA: &[u8] = include_bytes!("tls/other_ca.pem");
+
+fn cert_der() -> Vec<u8> {
+    rustls_pemfile::certs(&mut &CERT[..]).unwrap().remove(0)
+}
+
+fn server_cfg(versions: &[&'static SupportedProtocolVersion]) -> ServerConfig {
+    let certs = vec![rustls::Certificate(cert_der())];
+    let key = rustls_pemfile::pkcs8_private_keys(&mut &KEY[..])
+        .unwrap()
+        .remove(0);
+    ServerConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(versions)
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(certs, rustls::PrivateKey(key))
+        .unwrap()
+}
+
+// Version and cipher suite of a served request. None if client did not send one.
+type Session = Option<(ProtocolVersion, CipherSuite)>;
+
+// Serve one request over TLS with an empty 200 response.
+fn serve_tls(cfg: ServerConfig) -> (u16, thread::JoinHandle<Session>) {
+    server(move |listener| {
+        let mut s = accept(&listener);
+        let mut con = ServerConnection::new(Arc::new(cfg)).unwrap();
+        let mut tls = rustls::Stream::new(&mut con, &mut s);
+        let mut head = Vec::new();
+        let mut b = [0u8; 1];
+        while !head.ends_with(b"\r\n\r\n") {
+            match tls.read(&mut b) {
+                Ok(1) => head.push(b[0]),
+                _ => return None,
+            }
+        }
+        tls.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+        let suite = con.negotiated_cipher_suite().unwrap().suite();
+        Some((con.protocol_version().unwrap(), suite))
+    })
+}
+
+// Trust test CA only.
+fn ca_cfg() -> HttpcCfg {
+    let mut cfg = HttpcCfg::new();
+    cfg.pem_ca.push(CA.to_vec());
+    cfg
+}
+
+// GET https://127.0.0.1:port/ with SNI localhost. setup runs after url is set.
+fn get(cfg: HttpcCfg, port: u16, setup: fn(&mut CallBuilder)) -> mio_httpc::Result<u16> {
+    let mut htp = Httpc::new(0, Some(cfg));
+    let mut poll = mio::Poll::new().unwrap();
+    let mut cb = CallBuilder::get();
+    cb.timeout_ms(3000)
+        .url(&format!("https://127.0.0.1:{}/", port))
+        .unwrap()
+        .tls_sni("localhost");
+    setup(&mut cb);
+    exec_on(&mut htp, &mut poll, &mut cb).map(|(resp, _)| resp.status)
+}
+
+#[test]
+fn tls_versions() {
+    let (port, server) = serve_tls(server_cfg(&[&TLS12, &TLS13]));
+    assert_eq!(get(ca_cfg(), port, |_| {}).unwrap(), 200);
+    assert_eq!(server.join().unwrap().unwrap().0, ProtocolVersion::TLSv1_3);
+
+    let (port, server) = serve_tls(server_cfg(&[&TLS12, &TLS13]));
+    let r = get(ca_cfg(), port, |cb| {
+        cb.tls_max_version(TlsVersion::Tls12);
+    });
+    assert_eq!(r.unwrap(), 200);
+    assert_eq!(server.join().unwrap().unwrap().0, ProtocolVersion::TLSv1_2);
+
+    // Server without TLS 1.3.
+    let (port, server) = serve_tls(server_cfg(&[&TLS12]));
+    let r = get(ca_cfg(), port, |cb| {
+        cb.tls_min_version(TlsVersion::Tls13);
+    });
+    assert!(r.is_err());
+    assert!(server.join().unwrap().is_none());
+}
+
+#[test]
+fn tls_ciphers() {
+    let (port, server) = serve_tls(server_cfg(&[&TLS12, &TLS13]));
+    let r = get(ca_cfg(), port, |cb| {
+        cb.tls_ciphers(&["TLS13_CHACHA20_POLY1305_SHA256"]);
+    });
+    assert_eq!(r.unwrap(), 200);
+    let suite = server.join().unwrap().unwrap().1;
+    assert_eq!(suite, CipherSuite::TLS13_CHACHA20_POLY1305_SHA256);
+
+    let (port, server) = serve_tls(server_cfg(&[&TLS12, &TLS13]));
+    let r = get(ca_cfg(), port, |cb| {
+        cb.tls_max_version(TlsVersion::Tls12)
+            .tls_ciphers(&["tls_ecdhe_ecdsa_with_aes_128_gcm_sha256"]);
+    });
+    assert_eq!(r.unwrap(), 200);
+    let suite = server.join().unwrap().unwrap().1;
+    assert_eq!(suite, CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256);
+
+    // Nothing to offer. Listener is never served.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let r = get(ca_cfg(), port, |cb| {
+        cb.tls_ciphers(&["NOT_A_SUITE"]);
+    });
+    assert!(matches!(r, Err(Error::Other(_))), "{:?}", r);
+}
+
+#[test]
+fn tls_verifier() {
+    // Verifier accepts certificate that is not trusted.
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let mut cfg = HttpcCfg::new();
+    let seen1 = seen.clone();
+    cfg.verifier = Some(Arc::new(
+        move |host: &str, chain: &[Vec<u8>], ok: bool| -> Result<(), String> {
+            seen1
+                .lock()
+                .unwrap()
+                .push((host.to_string(), chain.to_vec(), ok));
+            Ok(())
+        },
+    ));
+    let (port, server) = serve_tls(server_cfg(&[&TLS12, &TLS13]));
+    assert_eq!(get(cfg, port, |_| {}).unwrap(), 200);
+    assert!(server.join().unwrap().is_some());
+    let seen = seen.lock().unwrap();
+    assert_eq!(seen.len(), 1);
+    assert_eq!(seen[0].0, "localhost");
+    assert_eq!(seen[0].1[0], cert_der());
+    assert!(!seen[0].2);
+
+    // Verifier rejects trusted certificate, request is not sent.
+    let mut cfg = ca_cfg();
+    cfg.verifier = Some(Arc::new(
+        |_: &str, _: &[Vec<u8>], ok: bool| -> Result<(), String> {
+            assert!(ok);
+            Err("nope".to_string())
+        },
+    ));
+    let (port, server) = serve_tls(server_cfg(&[&TLS12, &TLS13]));
+    match get(cfg, port, |_| {}) {
+        Err(Error::CertRejected(reason)) => assert_eq!(reason, "nope"),
+        r => panic!("{:?}", r),
+    }
+    assert!(server.join().unwrap().is_none());
+
+    // Untrusted without verifier.
+    let (port, server) = serve_tls(server_cfg(&[&TLS12, &TLS13]));
+    assert!(get(HttpcCfg::new(), port, |_| {}).is_err());
+    assert!(server.join().unwrap().is_none());
+}
+
+#[test]
+fn tls_pin_sets() {
+    fn pinned(set: PinSet) -> HttpcCfg {
+        let mut cfg = ca_cfg();
+        cfg.pin_sets.push(set);
+        cfg
+    }
+    fn get_api(cfg: HttpcCfg) -> (mio_httpc::Result<u16>, bool) {
+        let (port, server) = serve_tls(server_cfg(&[&TLS12, &TLS13]));
+        let r = get(cfg, port, |cb| {
+            cb.tls_sni("api.test.local");
+        });
+        (r, server.join().unwrap().is_some())
+    }
+    let wrong = "sha256/AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+
+    // Wildcard set applies, request is not sent on mismatch.
+    let (r, sent) = get_api(pinned(PinSet::new("*.test.local", &[wrong])));
+    let observed = match r {
+        Err(Error::InvalidPin(f)) => {
+            assert_eq!(f.host, "api.test.local");
+            assert_eq!(f.pin_host, "*.test.local");
+            assert!(!f.report_only);
+            f.observed
+        }
+        r => panic!("{:?}", r),
+    };
+    assert!(!sent);
+
+    let (r, sent) = get_api(pinned(PinSet::new("*.test.local", &[wrong, &observed[0]])));
+    assert_eq!(r.unwrap(), 200);
+    assert!(sent);
+
+    // Set for another host does not apply.
+    let (r, _) = get_api(pinned(PinSet::new("*.example.com", &[wrong])));
+    assert_eq!(r.unwrap(), 200);
+
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let mut cfg = pinned(PinSet::new("api.test.local", &[wrong]).report_only());
+    let reports1 = reports.clone();
+    cfg.pin_report = Some(Arc::new(move |f| {
+        reports1.lock().unwrap().push(f.clone());
+    }));
+    let (r, sent) = get_api(cfg);
+    assert_eq!(r.unwrap(), 200);
+    assert!(sent);
+    let reports = reports.lock().unwrap();
+    assert_eq!(reports.len(), 1);
+    assert!(reports[0].report_only);
+    assert_eq!(reports[0].observed, observed);
+}
+
+#[test]
+fn tls_root_certs() {
+    fn get_root(cfg: HttpcCfg) -> mio_httpc::Result<u16> {
+        let (port, _server) = serve_tls(server_cfg(&[&TLS12, &TLS13]));
+        get(cfg, port, |_| {})
+    }
+    // PEM bundle with test CA after another certificate.
+    let mut cfg = HttpcCfg::new();
+    cfg.pem_ca.push([OTHER_CA, CA].concat());
+    assert_eq!(get_root(cfg).unwrap(), 200);
+
+    let mut cfg = HttpcCfg::new();
+    cfg.pem_ca.push(OTHER_CA.to_vec());
+    assert!(get_root(cfg).is_err());
+
+    let mut cfg = HttpcCfg::new();
+    cfg.der_ca
+        .push(rustls_pemfile::certs(&mut &CA[..]).unwrap().remove(0));
+    assert_eq!(get_root(cfg).unwrap(), 200);
+
+    // System store is read from SSL_CERT_FILE. No other test uses it.
+    std::env::set_var(
+        "SSL_CERT_FILE",
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/tls/ca.pem"),
+    );
+    for &roots in [RootCerts::System, RootCerts::Both].iter() {
+        let mut cfg = HttpcCfg::new();
+        cfg.root_certs = roots;
+        assert_eq!(get_root(cfg).unwrap(), 200);
+    }
+}
@@ -0,0 +1,769 @@
+// Autobahn style WebSocket framing cases against an in-process server.
+mod common;
+
+use common::{accept, read_head, server};
+use data_encoding::BASE64;
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+use mio_httpc::{
+    CallBuilder, CloseCode, Error, HashType, Httpc, WSPacket, WebSocket, WsDeflate, WsFail,
+};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// Unmasked server frame.
+fn frame(b0: u8, payload: &[u8]) -> Vec<u8> {
+    let mut out = vec![b0];
+    if payload.len() < 126 {
+        out.push(payload.len() as u8);
+    } else if payload.len() < 65536 {
+        out.push(126);
+        out.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    } else {
+        out.push(127);
+        out.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+    }
+    out.extend_from_slice(payload);
+    out
+}
+
+fn close_frame(code: u16, reason: &str) -> Vec<u8> {
+    let mut payload = code.to_be_bytes().to_vec();
+    payload.extend_from_slice(reason.as_bytes());
+    frame(0x88, &payload)
+}
+
+// Read one masked client frame. Returns (opcode with RSV1 bit, payload).
+fn read_frame(s: &mut TcpStream) -> Option<(u8, Vec<u8>)> {
+    let mut hdr = [0u8; 2];
+    s.read_exact(&mut hdr).ok()?;
+    assert!(hdr[1] & 0x80 != 0, "client frame not masked");
+    let mut len = (hdr[1] & 0x7f) as u64;
+    if len == 126 {
+        let mut b = [0u8; 2];
+        s.read_exact(&mut b).ok()?;
+        len = u16::from_be_bytes(b) as u64;
+    } else if len == 127 {
+        let mut b = [0u8; 8];
+        s.read_exact(&mut b).ok()?;
+        len = u64::from_be_bytes(b);
+    }
+    let mut mask = [0u8; 4];
+    s.read_exact(&mut mask).ok()?;
+    let mut payload = vec![0u8; len as usize];
+    s.read_exact(&mut payload).ok()?;
+    for (i, b) in payload.iter_mut().enumerate() {
+        *b ^= mask[i & 3];
+    }
+    Some((hdr[0] & 0x4f, payload))
+}
+
+// Read handshake request and accept it with extra response headers hdrs.
+// Returns request head.
+fn handshake(s: &mut TcpStream, hdrs: &str) -> String {
+    let req = read_head(s);
+    let key = req
+        .lines()
+        .find(|l| l.to_ascii_lowercase().starts_with("sec-websocket-key:"))
+        .map(|l| l[18..].trim().to_string())
+        .unwrap();
+    let accept = BASE64.encode(&mio_httpc::hash(
+        HashType::SHA1,
+        format!("{}{}", key, GUID).as_bytes(),
+    ));
+    write!(
+        s,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+         Connection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n{}\r\n",
+        accept, hdrs
+    )
+    .unwrap();
+    req
+}
+
+// Accept a single WebSocket client, reply with extra headers hdrs and write frames.
+// Returns request head and frames client sent.
+fn serve(
+    hdrs: &'static str,
+    frames: Vec<Vec<u8>>,
+) -> (u16, thread::JoinHandle<(String, Vec<(u8, Vec<u8>)>)>) {
+    server(move |listener| {
+        let mut s = accept(&listener);
+        let req = handshake(&mut s, hdrs);
+        for f in frames {
+            s.write_all(&f).unwrap();
+        }
+        let mut out = Vec::new();
+        while let Some((op, payload)) = read_frame(&mut s) {
+            out.push((op, payload));
+            if op == 8 {
+                break;
+            }
+        }
+        (req, out)
+    })
+}
+
+struct Run {
+    // Handshake request.
+    req: String,
+    // What recv_message or recv_packet returned, in order.
+    got: Vec<String>,
+    err: Option<Error>,
+    status: Option<(CloseCode, String)>,
+    // Frames client sent to server.
+    sent: Vec<(u8, Vec<u8>)>,
+}
+
+fn run(frames: Vec<Vec<u8>>, setup: impl FnOnce(&mut CallBuilder)) -> Run {
+    run_ext("", frames, setup)
+}
+
+fn run_ext(hdrs: &'static str, frames: Vec<Vec<u8>>, setup: impl FnOnce(&mut CallBuilder)) -> Run {
+    run_recv(hdrs, frames, setup, false)
+}
+
+// Receive with recv_packet instead of recv_message.
+fn run_packets(frames: Vec<Vec<u8>>) -> Run {
+    run_recv("", frames, |_| {}, true)
+}
+
+fn run_recv(
+    hdrs: &'static str,
+    frames: Vec<Vec<u8>>,
+    setup: impl FnOnce(&mut CallBuilder),
+    packets: bool,
+) -> Run {
+    let (port, server) = serve(hdrs, frames);
+    let mut poll = mio::Poll::new().unwrap();
+    let mut htp = Httpc::new(0, None);
+    let mut cb = CallBuilder::get();
+    cb.url(&format!("ws://127.0.0.1:{}/", port)).unwrap();
+    setup(&mut cb);
+    let mut ws: WebSocket = cb.websocket(&mut htp, poll.registry()).unwrap();
+    let mut events = mio::Events::with_capacity(8);
+    let mut got = Vec::new();
+    let mut err = None;
+    let start = Instant::now();
+    'outer: while start.elapsed() < Duration::from_secs(3) {
+        poll.poll(&mut events, Some(Duration::from_millis(10)))
+            .unwrap();
+        for ev in events.iter() {
+            htp.event(&ev);
+        }
+        loop {
+            let pkt = if packets {
+                ws.recv_packet(&mut htp, poll.registry())
+            } else {
+                ws.recv_message(&mut htp, poll.registry())
+            };
+            match pkt {
+                Ok(WSPacket::None) => break,
+                Ok(WSPacket::Text(fin, t)) => got.push(format!("text {} {}", fin, t)),
+                Ok(WSPacket::Binary(fin, b)) => got.push(format!("bin {} {}", fin, b.len())),
+                Ok(WSPacket::Ping(p)) => got.push(format!("ping {}", p.len())),
+                Ok(WSPacket::Pong(p)) => got.push(format!("pong {}", p.len())),
+                Ok(WSPacket::Close(c, _)) => got.push(format!("close {:?}", c)),
+                Err(e) => {
+                    err = Some(e);
+                    break 'outer;
+                }
+            }
+        }
+    }
+    let status = ws.close_status().map(|(c, r)| (c, r.to_string()));
+    ws.finish(&mut htp);
+    let (req, sent) = server.join().unwrap();
+    Run {
+        req,
+        got,
+        err,
+        status,
+        sent,
+    }
+}
+
+// Client failed the connection with close code.
+fn assert_failed(r: &Run, code: u16) {
+    match r.err {
+        Some(Error::WebSocketProtocol(_)) => {}
+        ref e => panic!("expected protocol error, got {:?}", e),
+    }
+    let close = r.sent.last().expect("no close sent");
+    assert_eq!(close.0, 8);
+    assert_eq!(&close.1[..2], &code.to_be_bytes());
+}
+
+#[test]
+fn ws_text_and_binary() {
+    let big = vec![b'a'; 70000];
+    let r = run(
+        vec![
+            frame(0x81, b"hello"),
+            frame(0x82, &[1, 2, 3]),
+            frame(0x81, &big),
+            frame(0x81, b""),
+            close_frame(1000, ""),
+        ],
+        |_| {},
+    );
+    assert!(matches!(r.err, Some(Error::Closed)), "{:?}", r.err);
+    assert_eq!(r.got[0], "text true hello");
+    assert_eq!(r.got[1], "bin true 3");
+    assert_eq!(r.got[2].len(), "text true ".len() + big.len());
+    assert_eq!(r.got[3], "text true ");
+}
+
+#[test]
+fn ws_fragments_with_control_frames() {
+    // "€" split between fragments, ping in the middle of the message.
+    let r = run(
+        vec![
+            frame(0x01, b"a\xe2"),
+            frame(0x89, b"ping"),
+            frame(0x00, b"\x82"),
+            frame(0x80, b"\xacb"),
+            close_frame(1000, ""),
+        ],
+        |cb| {
+            cb.ws_auto_pong(true);
+        },
+    );
+    assert!(matches!(r.err, Some(Error::Closed)), "{:?}", r.err);
+    assert_eq!(r.got, vec!["ping 4", "text true a€b", "close Some(1000)"]);
+}
+
+#[test]
+fn ws_auto_pong_payload() {
+    let r = run(vec![frame(0x89, b"hello"), close_frame(1000, "")], |cb| {
+        cb.ws_auto_pong(true);
+    });
+    assert_eq!(r.sent[0], (10, b"hello".to_vec()));
+}
+
+#[test]
+fn ws_reserved_bits() {
+    assert_failed(&run(vec![frame(0xa1, b"rsv2")], |_| {}), 1002);
+    assert_failed(&run(vec![frame(0x91, b"rsv3")], |_| {}), 1002);
+    // RSV1 without permessage-deflate
+    assert_failed(&run(vec![frame(0xc1, b"rsv1")], |_| {}), 1002);
+}
+
+#[test]
+fn ws_reserved_opcodes() {
+    assert_failed(&run(vec![frame(0x83, b"")], |_| {}), 1002);
+    assert_failed(&run(vec![frame(0x8b, b"")], |_| {}), 1002);
+}
+
+#[test]
+fn ws_control_frames() {
+    assert_failed(&run(vec![frame(0x89, &[0u8; 126])], |_| {}), 1002);
+    assert_failed(&run(vec![frame(0x09, b"frag")], |_| {}), 1002);
+    // 125 bytes is allowed
+    let r = run(
+        vec![frame(0x89, &[0u8; 125]), close_frame(1000, "")],
+        |_| {},
+    );
+    assert_eq!(r.got[0], "ping 125");
+}
+
+#[test]
+fn ws_masked_server_frame() {
+    let f = vec![
+        0x81,
+        0x85,
+        1,
+        2,
+        3,
+        4,
+        b'h' ^ 1,
+        b'e' ^ 2,
+        b'l' ^ 3,
+        b'l' ^ 4,
+        b'o' ^ 1,
+    ];
+    assert_failed(&run(vec![f], |_| {}), 1002);
+}
+
+#[test]
+fn ws_frame_too_large() {
+    let mut f = vec![0x82, 127];
+    f.extend_from_slice(&(1u64 << 40).to_be_bytes());
+    assert_failed(&run(vec![f], |_| {}), 1002);
+}
+
+#[test]
+fn ws_fragment_sequence() {
+    // continuation without a message
+    assert_failed(&run(vec![frame(0x80, b"x")], |_| {}), 1002);
+    // new message while fragmented message is not finished
+    assert_failed(
+        &run(vec![frame(0x01, b"x"), frame(0x81, b"y")], |_| {}),
+        1002,
+    );
+}
+
+#[test]
+fn ws_invalid_utf8() {
+    assert_failed(&run(vec![frame(0x81, b"\xce\xba\xe1\xbd")], |_| {}), 1007);
+    assert_failed(
+        &run(vec![frame(0x01, b"\xce"), frame(0x80, b"\xce")], |_| {}),
+        1007,
+    );
+}
+
+#[test]
+fn ws_packet_utf8_fragments() {
+    // "€" split between three fragments, "ü" between last two.
+    let r = run_packets(vec![
+        frame(0x01, b"a\xe2"),
+        frame(0x89, b"ping"),
+        frame(0x00, b"\x82"),
+        frame(0x00, b"\xacb\xc3"),
+        frame(0x80, b"\xbc"),
+        close_frame(1000, ""),
+    ]);
+    assert!(matches!(r.err, Some(Error::Closed)), "{:?}", r.err);
+    assert_eq!(
+        r.got,
+        vec![
+            "text false a",
+            "ping 4",
+            "text false ",
+            "text false €b",
+            "text true ü",
+            "close Some(1000)"
+        ]
+    );
+    // Incomplete character at end of message.
+    assert_failed(
+        &run_packets(vec![frame(0x01, b"a"), frame(0x80, b"\xe2\x82")]),
+        1007,
+    );
+    // Invalid sequence is rejected without waiting for the next fragment.
+    assert_failed(
+        &run_packets(vec![frame(0x01, b"a\xe2\x28"), frame(0x80, b"b")]),
+        1007,
+    );
+}
+
+#[test]
+fn ws_close() {
+    let r = run(vec![close_frame(1000, "bye")], |_| {});
+    assert!(matches!(r.err, Some(Error::Closed)), "{:?}", r.err);
+    assert_eq!(r.got, vec!["close Some(1000)"]);
+    assert_eq!(r.status, Some((CloseCode::Normal, "bye".to_string())));
+    // close is echoed
+    assert_eq!(r.sent.last().unwrap().1, 1000u16.to_be_bytes().to_vec());
+
+    let r = run(vec![frame(0x88, b"")], |_| {});
+    assert_eq!(r.status, Some((CloseCode::NoStatus, String::new())));
+    assert_eq!(r.sent.last().unwrap(), &(8, Vec::new()));
+}
+
+#[test]
+fn ws_invalid_close() {
+    assert_failed(&run(vec![frame(0x88, b"\x03")], |_| {}), 1002);
+    assert_failed(&run(vec![close_frame(1005, "")], |_| {}), 1002);
+    assert_failed(&run(vec![close_frame(999, "")], |_| {}), 1002);
+    assert_failed(&run(vec![frame(0x88, b"\x03\xe8\xff")], |_| {}), 1007);
+}
+
+#[test]
+fn ws_max_message() {
+    let r = run(
+        vec![frame(0x01, &[b'a'; 600]), frame(0x80, &[b'a'; 600])],
+        |cb| {
+            cb.ws_max_message(1000);
+        },
+    );
+    assert!(matches!(r.err, Some(Error::ResponseTooBig)), "{:?}", r.err);
+    assert_eq!(r.sent.last().unwrap().1, 1009u16.to_be_bytes().to_vec());
+}
+
+// Perform until cond is true.
+fn wait(
+    poll: &mut mio::Poll,
+    htp: &mut Httpc,
+    ws: &mut WebSocket,
+    cond: fn(&mut WebSocket) -> bool,
+) {
+    let mut events = mio::Events::with_capacity(8);
+    let start = Instant::now();
+    while !cond(ws) {
+        assert!(start.elapsed() < Duration::from_secs(3), "wait timed out");
+        poll.poll(&mut events, Some(Duration::from_millis(10)))
+            .unwrap();
+        for ev in events.iter() {
+            htp.event(&ev);
+        }
+        ws.perform(htp, poll.registry()).unwrap();
+    }
+}
+
+fn connect(
+    poll: &mio::Poll,
+    htp: &mut Httpc,
+    port: u16,
+    setup: impl FnOnce(&mut CallBuilder),
+) -> WebSocket {
+    let mut cb = CallBuilder::get();
+    cb.url(&format!("ws://127.0.0.1:{}/", port)).unwrap();
+    setup(&mut cb);
+    cb.websocket(htp, poll.registry()).unwrap()
+}
+
+// (opcode, payload length) of frames client sent.
+fn sent_frames(server: thread::JoinHandle<(String, Vec<(u8, Vec<u8>)>)>) -> Vec<(u8, usize)> {
+    server
+        .join()
+        .unwrap()
+        .1
+        .iter()
+        .map(|(op, p)| (*op, p.len()))
+        .collect()
+}
+
+#[test]
+fn ws_send_queue() {
+    let (port, server) = serve("", Vec::new());
+    let mut poll = mio::Poll::new().unwrap();
+    let mut htp = Httpc::new(0, None);
+    let mut ws = connect(&poll, &mut htp, port, |cb| {
+        cb.ws_send_queue(100, 10);
+    });
+    let msg = "a".repeat(60);
+    ws.try_send_text(true, &msg).unwrap();
+    assert!(matches!(
+        ws.try_send_bin(true, msg.as_bytes()),
+        Err(Error::WouldBlock)
+    ));
+    // Limit only applies to try_send.
+    ws.send_bin(true, msg.as_bytes());
+    assert_eq!(ws.sendq_len(), 60 + 2 + 4 + 60 + 2 + 4);
+    assert!(!ws.drained());
+
+    wait(&mut poll, &mut htp, &mut ws, |ws| ws.drained());
+    assert_eq!(ws.sendq_len(), 0);
+    // Over limit, but queue is empty.
+    ws.try_send_bin(true, &[0u8; 200]).unwrap();
+    ws.close(Some(1000), None);
+    wait(&mut poll, &mut htp, &mut ws, |ws| ws.sendq_len() == 0);
+    ws.finish(&mut htp);
+    assert_eq!(
+        sent_frames(server),
+        vec![(1, 60), (2, 60), (2, 200), (8, 2)]
+    );
+}
+
+#[test]
+fn ws_send_queue_slow_reader() {
+    // Queue is sent in many partial writes, more than 1 MiB is sent before it empties.
+    let (port, server) = server(|listener| {
+        let mut s = accept(&listener);
+        handshake(&mut s, "");
+        thread::sleep(Duration::from_millis(200));
+        let mut got = Vec::new();
+        while got.len() < 8 {
+            let (op, payload) = read_frame(&mut s).unwrap();
+            got.push((
+                op,
+                payload.len(),
+                payload.iter().all(|&b| b == got.len() as u8),
+            ));
+            thread::sleep(Duration::from_millis(20));
+        }
+        got
+    });
+    let mut poll = mio::Poll::new().unwrap();
+    let mut htp = Httpc::new(0, None);
+    let mut ws = connect(&poll, &mut htp, port, |_| {});
+    for i in 0..8u8 {
+        ws.send_bin(true, &vec![i; 1024 * 1024]);
+    }
+    wait(&mut poll, &mut htp, &mut ws, |ws| ws.sendq_len() == 0);
+    ws.finish(&mut htp);
+    assert_eq!(server.join().unwrap(), vec![(2, 1024 * 1024, true); 8]);
+}
+
+#[test]
+fn ws_send_vectored() {
+    let (port, server) = serve("", Vec::new());
+    let mut poll = mio::Poll::new().unwrap();
+    let mut htp = Httpc::new(0, None);
+    let mut ws = connect(&poll, &mut htp, port, |_| {});
+    let reg = poll.registry().try_clone().unwrap();
+    // Queued until handshake is done.
+    let mut hdr = b"hdr:".to_vec();
+    let mut payload = vec![7u8; 300_000];
+    ws.send_bin_vectored(&mut htp, &reg, true, &mut [&mut hdr, &mut payload])
+        .unwrap();
+    wait(&mut poll, &mut htp, &mut ws, |ws| ws.is_active());
+
+    let (mut a, mut b, mut c) = (b"part1".to_vec(), b"part2".to_vec(), vec![1u8; 100_000]);
+    ws.send_bin_vectored(&mut htp, &reg, false, &mut [&mut a, &mut b])
+        .unwrap();
+    ws.send_bin_vectored(&mut htp, &reg, true, &mut [&mut c])
+        .unwrap();
+    ws.close(Some(1000), None);
+    wait(&mut poll, &mut htp, &mut ws, |ws| ws.sendq_len() == 0);
+    ws.finish(&mut htp);
+    let (_, frames) = server.join().unwrap();
+    let mut expect = b"hdr:".to_vec();
+    expect.extend_from_slice(&[7u8; 300_000]);
+    assert_eq!(frames[0], (2, expect));
+    assert_eq!(frames[1], (2, b"part1part2".to_vec()));
+    assert_eq!(frames[2], (0, vec![1u8; 100_000]));
+    assert_eq!(frames[3].0, 8);
+}
+
+// Compress message with a fresh context like a server with no context takeover.
+fn deflate(msg: &[u8]) -> Vec<u8> {
+    let mut c = Compress::new(Compression::default(), false);
+    let mut out = Vec::with_capacity(msg.len() + 64);
+    c.compress_vec(msg, &mut out, FlushCompress::Sync).unwrap();
+    assert!(out.ends_with(&[0, 0, 0xff, 0xff]));
+    out.truncate(out.len() - 4);
+    out
+}
+
+fn inflate(payload: &[u8]) -> Vec<u8> {
+    let mut input = payload.to_vec();
+    input.extend_from_slice(&[0, 0, 0xff, 0xff]);
+    let mut out = Vec::with_capacity(input.len() * 10 + 1024);
+    Decompress::new(false)
+        .decompress_vec(&input, &mut out, FlushDecompress::Sync)
+        .unwrap();
+    out
+}
+
+const DEFLATE_EXT: &str =
+    "Sec-WebSocket-Extensions: permessage-deflate; server_no_context_takeover\r\n";
+
+fn ws_deflate_opts(cb: &mut CallBuilder) {
+    cb.ws_deflate(WsDeflate {
+        server_no_context_takeover: true,
+        ..Default::default()
+    });
+}
+
+#[test]
+fn ws_deflate_recv() {
+    let msg = "hello hello hello hello €";
+    let c = deflate(msg.as_bytes());
+    let r = run_ext(
+        DEFLATE_EXT,
+        vec![
+            frame(0xc1, &c),
+            frame(0x81, b"plain"),
+            // RSV1 only on first fragment
+            frame(0x41, &c[..4]),
+            frame(0x80, &c[4..]),
+            close_frame(1000, ""),
+        ],
+        ws_deflate_opts,
+    );
+    assert!(matches!(r.err, Some(Error::Closed)), "{:?}", r.err);
+    assert!(
+        r.req.contains(
+            "\r\nSec-WebSocket-Extensions: permessage-deflate; server_no_context_takeover\r\n"
+        ),
+        "{}",
+        r.req
+    );
+    let text = format!("text true {}", msg);
+    assert_eq!(
+        r.got,
+        vec![&text, "text true plain", &text, "close Some(1000)"]
+    );
+
+    // RSV1 on continuation or control frame
+    let r = run_ext(
+        DEFLATE_EXT,
+        vec![frame(0x41, &c[..4]), frame(0xc0, &c[4..])],
+        ws_deflate_opts,
+    );
+    assert_failed(&r, 1002);
+    assert_failed(
+        &run_ext(DEFLATE_EXT, vec![frame(0xc9, b"")], ws_deflate_opts),
+        1002,
+    );
+}
+
+#[test]
+fn ws_deflate_negotiation() {
+    let fail = |hdrs: &'static str, setup: fn(&mut CallBuilder)| {
+        let r = run_ext(hdrs, Vec::new(), setup);
+        assert!(
+            matches!(r.err, Some(Error::WebSocketFail(WsFail::Extension, _))),
+            "{:?}",
+            r.err
+        );
+    };
+    // Not offered
+    fail(DEFLATE_EXT, |_| {});
+    // client_max_window_bits was not offered
+    fail(
+        "Sec-WebSocket-Extensions: permessage-deflate; client_max_window_bits=10\r\n",
+        ws_deflate_opts,
+    );
+    fail(
+        "Sec-WebSocket-Extensions: permessage-deflate; server_max_window_bits=16\r\n",
+        ws_deflate_opts,
+    );
+    fail(
+        "Sec-WebSocket-Extensions: permessage-deflate, permessage-deflate\r\n",
+        ws_deflate_opts,
+    );
+
+    // Server did not accept, messages are not compressed.
+    let r = run(
+        vec![frame(0x81, b"plain"), close_frame(1000, "")],
+        ws_deflate_opts,
+    );
+    assert_eq!(r.got, vec!["text true plain", "close Some(1000)"]);
+}
+
+#[test]
+fn ws_deflate_send() {
+    let (port, server) = serve(
+        "Sec-WebSocket-Extensions: permessage-deflate; client_no_context_takeover\r\n",
+        Vec::new(),
+    );
+    let mut poll = mio::Poll::new().unwrap();
+    let mut htp = Httpc::new(0, None);
+    let mut ws = connect(&poll, &mut htp, port, |cb| {
+        cb.ws_deflate(WsDeflate::default());
+    });
+    wait(&mut poll, &mut htp, &mut ws, |ws| ws.is_active());
+    assert!(ws.is_deflate());
+    let msg = "a".repeat(1000);
+    ws.send_text(true, &msg);
+    ws.send_text(false, "frag");
+    ws.send_text(true, "ment");
+    ws.send_bin(true, msg.as_bytes());
+    ws.close(Some(1000), None);
+    wait(&mut poll, &mut htp, &mut ws, |ws| ws.sendq_len() == 0);
+    ws.finish(&mut htp);
+    let (req, frames) = server.join().unwrap();
+    assert!(
+        req.contains("\r\nSec-WebSocket-Extensions: permessage-deflate\r\n"),
+        "{}",
+        req
+    );
+    assert_eq!(frames[0].0, 0x41);
+    assert!(frames[0].1.len() < 100);
+    assert_eq!(inflate(&frames[0].1), msg.as_bytes());
+    assert_eq!((frames[1].0, frames[2].0), (0x41, 0x00));
+    assert_eq!(
+        inflate(&[&frames[1].1[..], &frames[2].1[..]].concat()),
+        b"fragment"
+    );
+    // Context is reset after every message, same input gives same output.
+    assert_eq!(frames[3], (0x42, frames[0].1.clone()));
+    assert_eq!(frames[4].0, 8);
+}
+
+#[test]
+fn ws_keepalive() {
+    // Reply to first ping only, return (opcode, time) of client frames.
+    let (port, server) = server(|listener| {
+        let mut s = accept(&listener);
+        handshake(&mut s, "");
+        let mut got = Vec::new();
+        while let Some((op, payload)) = read_frame(&mut s) {
+            if op == 9 && got.is_empty() {
+                s.write_all(&frame(0x8a, &payload)).unwrap();
+            }
+            got.push((op, Instant::now()));
+        }
+        got
+    });
+    let mut poll = mio::Poll::new().unwrap();
+    let mut htp = Httpc::new(0, None);
+    let interval = Duration::from_millis(100);
+    let timeout = Duration::from_millis(200);
+    let mut ws = connect(&poll, &mut htp, port, |cb| {
+        cb.ws_keepalive(interval, timeout);
+    });
+    assert!(ws.keepalive_deadline().is_none());
+    wait(&mut poll, &mut htp, &mut ws, |ws| ws.is_active());
+    let start = Instant::now();
+    assert!(ws.keepalive_deadline().unwrap() <= start + interval);
+
+    let mut events = mio::Events::with_capacity(8);
+    let err = loop {
+        assert!(
+            start.elapsed() < Duration::from_secs(3),
+            "no keepalive timeout"
+        );
+        let wait = ws
+            .keepalive_deadline()
+            .map(|d| d.saturating_duration_since(Instant::now()));
+        poll.poll(&mut events, wait).unwrap();
+        for ev in events.iter() {
+            htp.event(&ev);
+        }
+        if let Err(e) = ws.perform(&mut htp, poll.registry()) {
+            break e;
+        }
+    };
+    let dur = start.elapsed();
+    assert!(matches!(err, Error::WebSocketTimeout), "{:?}", err);
+    // ping, pong, ping after interval, timeout
+    assert!(dur >= interval * 2 + timeout, "{:?}", dur);
+    ws.finish(&mut htp);
+    let got = server.join().unwrap();
+    let ops: Vec<u8> = got.iter().map(|g| g.0).collect();
+    assert_eq!(ops, vec![9, 9]);
+    assert!(got[1].1 - got[0].1 >= interval);
+}
+
+#[test]
+fn ws_keepalive_from_timeout() {
+    // Peer answers first ping then goes silent. Only Httpc::timeout wakes the WebSocket.
+    let (port, server) = server(|listener| {
+        let mut s = accept(&listener);
+        handshake(&mut s, "");
+        let mut got = Vec::new();
+        while let Some((op, payload)) = read_frame(&mut s) {
+            if op == 9 && got.is_empty() {
+                s.write_all(&frame(0x8a, &payload)).unwrap();
+            }
+            got.push(op);
+        }
+        got
+    });
+    let mut poll = mio::Poll::new().unwrap();
+    let mut htp = Httpc::new(0, None);
+    let mut ws = connect(&poll, &mut htp, port, |cb| {
+        cb.ws_keepalive(Duration::from_millis(100), Duration::from_millis(200));
+    });
+    wait(&mut poll, &mut htp, &mut ws, |ws| ws.is_active());
+    let start = Instant::now();
+    let mut events = mio::Events::with_capacity(8);
+    let err = loop {
+        assert!(
+            start.elapsed() < Duration::from_secs(3),
+            "no keepalive timeout"
+        );
+        poll.poll(&mut events, Some(Duration::from_millis(20)))
+            .unwrap();
+        let mut ready = false;
+        for ev in events.iter() {
+            ready |= ws.is_call(&htp.event(&ev));
+        }
+        ready |= htp.timeout().into_iter().any(|c| ws.is_ref(c));
+        if ready {
+            if let Err(e) = ws.perform(&mut htp, poll.registry()) {
+                break e;
+            }
+        }
+    };
+    assert!(matches!(err, Error::WebSocketTimeout), "{:?}", err);
+    ws.finish(&mut htp);
+    assert_eq!(server.join().unwrap(), vec![9, 9]);
+}